@@ -1,10 +1,11 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use enum_map::enum_map;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
 use ludo_engine::{
     board::{Board, Team},
-    bots::average_bot,
+    bots::{all_seats, average_bot},
     game_state::GameState,
+    rollout::{RolloutEngine, Rollouts},
     simulator::get_ranked_actions,
+    utils::RolloutDice,
     Player,
 };
 
@@ -16,17 +17,37 @@ pub fn simulator_benchmark(c: &mut Criterion) {
             get_ranked_actions(
                 gs.clone(),
                 1,
-                enum_map! {_ => average_bot},
-                Team::FirstThird,
+                all_seats(average_bot),
+                Team::Odd.into(),
                 black_box(100),
+                0,
             )
         })
     });
 }
 
+pub fn rollout_benchmark(c: &mut Criterion) {
+    let rollouts = Rollouts::new(
+        GameState::new(Board::default(), Player::First),
+        Team::Odd.into(),
+        all_seats(average_bot),
+        RolloutDice::default(),
+        0,
+    );
+    let engine = RolloutEngine::default();
+
+    // Reported in games per second.
+    let mut group = c.benchmark_group("rollouts");
+    group.throughput(Throughput::Elements(200));
+    group.bench_function("engine", |b| {
+        b.iter(|| engine.run(&[(&rollouts, black_box(0..200))]))
+    });
+    group.finish();
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default().sample_size(10);
-    targets= simulator_benchmark
+    targets= simulator_benchmark, rollout_benchmark
 }
 criterion_main!(benches);
@@ -1,10 +1,12 @@
-use crate::positions::{LAST_PLACE, POSITIONS};
-use crate::{positions::SAFE_SPOTS, Player};
+use crate::positions::{Geometry, MAX_SQUARES};
+use crate::rules::RuleSet;
+use crate::zobrist;
+use crate::Player;
+use arrayvec::ArrayVec;
 use enum_map::{enum_map, Enum, EnumMap};
 use extend::ext;
 use serde::ser::SerializeMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
 
 use std::hash::Hasher;
 use std::{collections::HashMap, hash::Hash, ops::Index};
@@ -12,6 +14,13 @@ use std::{collections::HashMap, hash::Hash, ops::Index};
 pub type PiecePosition = u8;
 pub type PieceLocation = (i8, i8);
 
+/// Most actions a roll can allow: one for each piece of the player and of
+/// up to two teammates.
+pub const MAX_ACTIONS: usize = 12;
+
+/// Actions a roll allows, ordered by player and piece.
+pub type Actions = ArrayVec<Action, MAX_ACTIONS>;
+
 #[ext]
 pub impl PiecePosition {
     fn is_first(&self) -> bool {
@@ -22,23 +31,24 @@ pub impl PiecePosition {
         *self == 1u8
     }
 
-    fn is_last(&self) -> bool {
-        *self == LAST_PLACE
+    fn is_last(&self, geometry: Geometry) -> bool {
+        *self == geometry.last_place()
     }
 
-    fn get_coords(&self, player: Player) -> PieceLocation {
-        POSITIONS[player][*self as usize]
+    fn get_coords(&self, player: Player, geometry: Geometry) -> PieceLocation {
+        geometry.positions(player)[*self as usize]
     }
 }
 
 #[ext]
 pub impl PieceLocation {
-    fn is_safe(&self) -> bool {
-        SAFE_SPOTS.contains(self)
+    fn is_safe(&self, rules: &RuleSet) -> bool {
+        rules.is_safe(*self)
     }
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, serde::Serialize)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct PlayerData {
     pub pieces_positions: [PiecePosition; 4],
 }
@@ -51,7 +61,9 @@ impl Index<u8> for PlayerData {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Copy, Debug)]
+/// Serialised as a map from each player to their pieces, and players missing
+/// from it are left in their yard. Saved games only write the seated players.
+#[derive(Clone, PartialEq, Eq, Hash, Copy, Debug)]
 pub struct Board {
     pub players: EnumMap<Player, PlayerData>,
 }
@@ -61,7 +73,7 @@ impl serde::ser::Serialize for Board {
     where
         S: serde::Serializer,
     {
-        let mut map = serializer.serialize_map(Some(4))?;
+        let mut map = serializer.serialize_map(Some(self.players.len()))?;
         for (player, data) in self.players {
             map.serialize_entry(&player, &data)?;
         }
@@ -69,14 +81,25 @@ impl serde::ser::Serialize for Board {
     }
 }
 
+impl<'de> serde::de::Deserialize<'de> for Board {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let players = HashMap::<Player, PlayerData>::deserialize(deserializer)?;
+        let mut board = Board::default();
+        for (player, data) in players {
+            board.players[player] = data;
+        }
+        Ok(board)
+    }
+}
+
 impl Default for Board {
     fn default() -> Self {
         Self {
             players: enum_map! {
-                Player::First => PlayerData::default(),
-                Player::Second => PlayerData::default(),
-                Player::Third => PlayerData::default(),
-                Player::Fourth => PlayerData::default(),
+                _ => PlayerData::default(),
             },
         }
     }
@@ -103,6 +126,214 @@ impl Hash for Action {
     }
 }
 
+/// What happened when an action was applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MoveOutcome {
+    /// Owner and index of the piece sent back to the yard.
+    pub captured: Option<(Player, u8)>,
+    /// Whether the same player rolls again.
+    ///
+    /// From `Board::apply_action` this only covers the capture and finish
+    /// bonuses; `GameState` also accounts for sixes and finished players.
+    pub another_turn: bool,
+    /// Whether the piece reached the last place.
+    pub finished: bool,
+}
+
+/// Why an action can't be played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IllegalMove {
+    /// Dice outside `1..=6`.
+    BadDice(u8),
+    /// A piece the player to move isn't allowed to move.
+    WrongPlayer {
+        player: Player,
+        turn: Player,
+    },
+    NoSuchPiece(u8),
+    /// The piece isn't where the action says it starts.
+    StaleFrom {
+        expected: PiecePosition,
+        found: PiecePosition,
+    },
+    /// The move goes past the last place.
+    Overshoot {
+        to: PiecePosition,
+        last: PiecePosition,
+    },
+    /// The action doesn't move the piece by the dice roll.
+    WrongDestination {
+        expected: PiecePosition,
+        found: PiecePosition,
+    },
+    /// A piece that can't be captured is on the destination.
+    Blocked {
+        location: PieceLocation,
+        occupant: Player,
+    },
+    /// Pieces in the yard can only come out with one of these rolls.
+    EntryNeeds(Vec<u8>),
+    /// The player rolled too many sixes in a row to move again.
+    SixesExhausted,
+    /// The action chooser picked an index outside the actions it was given.
+    NoSuchChoice {
+        index: usize,
+        choices: usize,
+    },
+}
+
+impl std::fmt::Display for IllegalMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IllegalMove::BadDice(dice) => write!(f, "{} isn't a dice roll", dice),
+            IllegalMove::WrongPlayer { player, turn } => {
+                write!(f, "{:?} can't move {:?}'s pieces now", turn, player)
+            }
+            IllegalMove::NoSuchPiece(piece) => write!(f, "there's no piece {}", piece),
+            IllegalMove::StaleFrom { expected, found } => {
+                write!(f, "piece is at {}, not at {}", found, expected)
+            }
+            IllegalMove::Overshoot { to, last } => {
+                write!(f, "{} is past the last place {}", to, last)
+            }
+            IllegalMove::WrongDestination { expected, found } => {
+                write!(f, "piece should land on {}, not on {}", expected, found)
+            }
+            IllegalMove::Blocked { location, occupant } => {
+                write!(f, "{:?} is blocked by {:?}", location, occupant)
+            }
+            IllegalMove::EntryNeeds(rolls) => {
+                write!(f, "leaving the yard needs one of {:?}", rolls)
+            }
+            IllegalMove::SixesExhausted => write!(f, "too many sixes in a row"),
+            IllegalMove::NoSuchChoice { index, choices } => {
+                write!(f, "chose action {} out of {}", index, choices)
+            }
+        }
+    }
+}
+
+impl std::error::Error for IllegalMove {}
+
+/// What a piece would find on the square it moves to.
+#[derive(Debug, PartialEq, Eq)]
+enum Landing {
+    Free,
+    Capture(Player, u8),
+    Blocked(Player),
+}
+
+/// The piece on each square of a board, so that what a move lands on is
+/// found without going through every piece, and the board's Zobrist key.
+///
+/// Only remembers the geometry and seats it was built for: keeping it in
+/// step with the board is up to its owner, which updates it as actions are
+/// applied and drops it when the board is changed some other way.
+#[derive(Clone, Copy, Debug)]
+pub struct Occupancy {
+    /// `0` for empty squares, or one more than the player's index times
+    /// four plus the piece's.
+    squares: [u8; MAX_SQUARES],
+    geometry: Geometry,
+    /// Bit set of the seated players.
+    seats: u8,
+    key: u64,
+}
+
+fn seat_bits(rules: &RuleSet) -> u8 {
+    rules
+        .seats
+        .iter()
+        .fold(0, |bits, &player| bits | 1 << player as u8)
+}
+
+impl Occupancy {
+    pub fn new(board: &Board, rules: &RuleSet) -> Self {
+        let mut occupancy = Self {
+            squares: [0; MAX_SQUARES],
+            geometry: rules.geometry,
+            seats: seat_bits(rules),
+            key: board.key(),
+        };
+        for &player in &rules.seats {
+            let numbers = rules.geometry.squares(player);
+            for (piece, &position) in board.players[player].pieces_positions.iter().enumerate() {
+                // The first piece found keeps the square, as when searching.
+                if let Some(square) = numbers
+                    .get(position as usize)
+                    .and_then(|&square| occupancy.squares.get_mut(square as usize))
+                    .filter(|square| **square == 0)
+                {
+                    *square = player as u8 * 4 + piece as u8 + 1;
+                }
+            }
+        }
+        occupancy
+    }
+
+    /// Whether this was built for the geometry and seats of `rules`.
+    pub fn fits(&self, rules: &RuleSet) -> bool {
+        self.geometry == rules.geometry && self.seats == seat_bits(rules)
+    }
+
+    /// Zobrist key of the board.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Owner and index of the piece on the square `player` reaches at `to`.
+    pub fn get(&self, player: Player, to: PiecePosition) -> Option<(Player, u8)> {
+        let square = *self.geometry.squares(player).get(to as usize)?;
+        match *self.squares.get(square as usize)? {
+            0 => None,
+            code => Some((Player::from_usize((code as usize - 1) / 4), (code - 1) % 4)),
+        }
+    }
+
+    fn set(&mut self, player: Player, position: PiecePosition, code: u8) {
+        let square = self.geometry.squares(player)[position as usize];
+        if let Some(square) = self.squares.get_mut(square as usize) {
+            *square = code;
+        }
+    }
+
+    /// Follows `action` being applied to `before`, the board it described,
+    /// with `outcome`.
+    pub fn apply(&mut self, before: &Board, action: Action, outcome: MoveOutcome) {
+        if let Some((player, piece)) = outcome.captured {
+            let position = before.players[player][piece];
+            self.set(player, position, 0);
+            self.key ^= zobrist::piece(player, piece, position) ^ zobrist::piece(player, piece, 0);
+        }
+        let code = action.player as u8 * 4 + action.piece + 1;
+        self.set(action.player, action.from, 0);
+        self.set(action.player, action.to, code);
+        self.key ^= zobrist::piece(action.player, action.piece, action.from)
+            ^ zobrist::piece(action.player, action.piece, action.to);
+    }
+
+    fn landing(
+        &self,
+        board: &Board,
+        player: Player,
+        to: PiecePosition,
+        rules: &RuleSet,
+    ) -> Landing {
+        let Some((occupant, piece)) = self.get(player, to) else {
+            return Landing::Free;
+        };
+        // The occupant's piece on its own start square is on its position 1.
+        if occupant.is_enemy_of(player, rules)
+            && !to.get_coords(player, rules.geometry).is_safe(rules)
+            && !board.players[occupant][piece].is_home()
+        {
+            Landing::Capture(occupant, piece)
+        } else {
+            Landing::Blocked(occupant)
+        }
+    }
+}
+
 pub struct RenderedBoard {
     pub board: HashMap<(i8, i8), (Player, u8)>,
 }
@@ -118,84 +349,138 @@ enum WhoCanMove {
     AnyInTeam,
 }
 
-#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+/// Teams alternate around the board, so four seats play two against two and
+/// six seats three against three.
+#[derive(Serialize, Deserialize, Enum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Team {
-    FirstThird,
-    SecondFourth,
+    /// First, Third and Fifth.
+    Odd,
+    /// Second, Fourth and Sixth.
+    Even,
 }
 
 impl Team {
     pub fn get_players(&self) -> Vec<Player> {
         match self {
-            Team::FirstThird => vec![Player::First, Player::Third],
-            Team::SecondFourth => vec![Player::Second, Player::Fourth],
+            Team::Odd => vec![Player::First, Player::Third, Player::Fifth],
+            Team::Even => vec![Player::Second, Player::Fourth, Player::Sixth],
         }
     }
 }
 
+/// Who plays for a result: a team, or a lone player when playing without teams.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Side {
+    Team(Team),
+    Player(Player),
+}
+
+impl Side {
+    pub fn contains(&self, player: Player) -> bool {
+        match self {
+            Side::Team(team) => team.get_players().contains(&player),
+            Side::Player(p) => *p == player,
+        }
+    }
+}
+
+impl From<Team> for Side {
+    fn from(team: Team) -> Self {
+        Side::Team(team)
+    }
+}
+
+impl From<Player> for Side {
+    fn from(player: Player) -> Self {
+        Side::Player(player)
+    }
+}
+
 impl Board {
-    pub fn render_board(&self) -> RenderedBoard {
+    pub fn render_board(&self, rules: &RuleSet) -> RenderedBoard {
         let mut board = HashMap::new();
         for (player, player_data) in &self.players {
+            if !rules.is_seated(player) {
+                continue;
+            }
             for (piece_index, &piece) in player_data.pieces_positions.iter().enumerate() {
                 let piece_index = piece_index as u8;
-                board.insert(piece.get_coords(player), (player, piece_index));
+                board.insert(
+                    piece.get_coords(player, rules.geometry),
+                    (player, piece_index),
+                );
             }
         }
         RenderedBoard { board }
     }
 
-    pub fn who_won(&self) -> Option<Team> {
-        for team in [Team::FirstThird, Team::SecondFourth] {
-            let players = team.get_players();
-            let goals = self
-                .players
-                .iter()
-                .filter(|(player, _)| players.contains(player))
-                .flat_map(|(_, data)| data.pieces_positions)
-                .filter(|&pos| pos == LAST_PLACE)
-                .count();
-            if goals >= 4 {
+    /// Zobrist key of where every piece is, seated or not.
+    pub fn key(&self) -> u64 {
+        let mut key = 0;
+        for (player, data) in &self.players {
+            for (piece, &position) in data.pieces_positions.iter().enumerate() {
+                key ^= zobrist::piece(player, piece as u8, position);
+            }
+        }
+        key
+    }
+
+    pub fn has_finished(&self, player: Player, rules: &RuleSet) -> bool {
+        self.players[player]
+            .pieces_positions
+            .iter()
+            .all(|pos| pos.is_last(rules.geometry))
+    }
+
+    pub fn who_won(&self, rules: &RuleSet) -> Option<Team> {
+        if !rules.team_mode {
+            return None;
+        }
+        for team in [Team::Odd, Team::Even] {
+            let players = team
+                .get_players()
+                .into_iter()
+                .filter(|&player| rules.is_seated(player))
+                .collect::<Vec<_>>();
+            if !players.is_empty()
+                && players
+                    .iter()
+                    .all(|&player| self.has_finished(player, rules))
+            {
                 return Some(team);
             }
         }
         None
     }
 
-    fn can_act(&self, player: Player, piece: u8, moves: u8) -> Option<(Action, WhoCanMove)> {
+    fn can_act(
+        &self,
+        occupancy: &Occupancy,
+        player: Player,
+        piece: u8,
+        moves: u8,
+        rules: &RuleSet,
+    ) -> Option<(Action, WhoCanMove)> {
         let pos = self.players[player].pieces_positions[piece as usize];
+        let geometry = rules.geometry;
+        let last_place = geometry.last_place();
         let new_pos_index = if pos == 0 {
-            if moves == 1 || moves == 6 {
+            if rules.can_enter(moves) {
                 Some(1)
             } else {
                 None
             }
-        } else if pos + moves > LAST_PLACE {
+        } else if pos + moves > last_place {
             None
         } else {
             Some(pos + moves)
         }?;
-        let board = self.render_board();
-        let who_can_move = if new_pos_index == LAST_PLACE || pos == 0 {
+        let who_can_move = if new_pos_index == last_place || pos == 0 {
             WhoCanMove::AnyInTeam
         } else {
             WhoCanMove::SamePlayer
         };
-        let new_pos = new_pos_index.get_coords(player);
-        let occupied_place = if let Some((occupant_player, _)) = board.get_position(new_pos) {
-            if new_pos_index != LAST_PLACE
-                && (occupant_player.is_friendly_to(player)
-                    || new_pos.is_safe()
-                    || POSITIONS[occupant_player][1] == new_pos)
-            {
-                true
-            } else {
-                false
-            }
-        } else {
-            false
-        };
-        if occupied_place {
+        if let Landing::Blocked(_) = occupancy.landing(self, player, new_pos_index, rules) {
             return None;
         }
         Some((
@@ -209,74 +494,274 @@ impl Board {
         ))
     }
 
-    pub fn actions_for_player(&self, moves: u8, player: Player) -> HashSet<Action> {
-        let mut actions = HashSet::<Action>::new();
-        let teammate = player.teammate();
-        for i in 0u8..4 {
-            if let Some((action, who_can_move)) = self.can_act(teammate, i, moves) {
-                if who_can_move == WhoCanMove::AnyInTeam {
-                    actions.insert(action);
-                }
+    pub fn actions_for_player(&self, moves: u8, player: Player, rules: &RuleSet) -> Actions {
+        self.actions_with(&Occupancy::new(self, rules), moves, player, rules)
+    }
+
+    /// Same as `actions_for_player`, looking squares up in `occupancy`,
+    /// which has to describe this board.
+    pub fn actions_with(
+        &self,
+        occupancy: &Occupancy,
+        moves: u8,
+        player: Player,
+        rules: &RuleSet,
+    ) -> Actions {
+        let mut actions = Actions::new();
+        if !rules.is_seated(player) {
+            return actions;
+        }
+        for mover in enum_iterator::all::<Player>() {
+            let teammate = mover != player;
+            if teammate && !(rules.is_seated(mover) && mover.is_friendly_to(player, rules)) {
+                continue;
             }
-            if let Some((action, _)) = self.can_act(player, i, moves) {
-                actions.insert(action);
+            let pieces = self.players[mover].pieces_positions;
+            for piece in 0..4u8 {
+                // Pieces in the same place make the same move, and actions
+                // are told apart by where they start.
+                if pieces[..piece as usize].contains(&pieces[piece as usize]) {
+                    continue;
+                }
+                if let Some((action, who_can_move)) =
+                    self.can_act(occupancy, mover, piece, moves, rules)
+                {
+                    if !teammate || who_can_move == WhoCanMove::AnyInTeam {
+                        actions.push(action);
+                    }
+                }
             }
         }
-
         actions
     }
 
-    // returns true if player has another turn.
-    pub fn apply_action(&mut self, action: Action) -> bool {
-        let new_pos = action.to.get_coords(action.player);
-        let mut another_turn = false;
-        if action.to < LAST_PLACE {
-            if let Some((occupant_player, occupant_piece)) =
-                self.render_board().get_position(new_pos)
-            {
-                if occupant_player.is_enemy_of(action.player)
-                    && !new_pos.is_safe()
-                    && POSITIONS[occupant_player][1] != new_pos
-                {
-                    self.players[occupant_player].pieces_positions[occupant_piece as usize] = 0;
-                    another_turn = true;
-                } else {
-                    return false;
-                }
+    /// Moves a piece, capturing whatever enemy piece it lands on.
+    ///
+    /// Only checks what the board alone can tell: that the piece is where
+    /// the action says and that it can land where it goes. Whose turn it
+    /// is and the dice are up to `GameState::apply_action`.
+    pub fn apply_action(
+        &mut self,
+        action: Action,
+        rules: &RuleSet,
+    ) -> Result<MoveOutcome, IllegalMove> {
+        self.apply_action_with(&Occupancy::new(self, rules), action, rules)
+    }
+
+    /// Same as `apply_action`, looking the square moved to up in
+    /// `occupancy`, which has to describe this board.
+    pub fn apply_action_with(
+        &mut self,
+        occupancy: &Occupancy,
+        action: Action,
+        rules: &RuleSet,
+    ) -> Result<MoveOutcome, IllegalMove> {
+        let geometry = rules.geometry;
+        if action.piece >= 4 {
+            return Err(IllegalMove::NoSuchPiece(action.piece));
+        }
+        let found = self.players[action.player].pieces_positions[action.piece as usize];
+        if found != action.from {
+            return Err(IllegalMove::StaleFrom {
+                expected: action.from,
+                found,
+            });
+        }
+        if action.to > geometry.last_place() {
+            return Err(IllegalMove::Overshoot {
+                to: action.to,
+                last: geometry.last_place(),
+            });
+        }
+
+        let mut outcome = MoveOutcome::default();
+        match occupancy.landing(self, action.player, action.to, rules) {
+            Landing::Free => {}
+            Landing::Capture(occupant, piece) => {
+                self.players[occupant].pieces_positions[piece as usize] = 0;
+                outcome.captured = Some((occupant, piece));
+                outcome.another_turn = rules.capture_bonus;
+            }
+            Landing::Blocked(occupant) => {
+                return Err(IllegalMove::Blocked {
+                    location: action.to.get_coords(action.player, geometry),
+                    occupant,
+                })
             }
         }
         self.players[action.player].pieces_positions[action.piece as usize] = action.to;
-        if action.to == LAST_PLACE {
-            another_turn = true;
+        if action.to.is_last(geometry) {
+            outcome.finished = true;
+            outcome.another_turn |= rules.finish_bonus;
         }
-        return another_turn;
+        Ok(outcome)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Board;
-    use crate::{board::Action, Player};
+    use super::{Board, Landing, PieceLocationExt, PiecePosition, PiecePositionExt};
+    use crate::{
+        board::{Action, IllegalMove, Occupancy},
+        game_state::GameState,
+        rules::RuleSet,
+        Player,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// What `Occupancy::landing` looks up, found by going through every
+    /// piece instead.
+    fn scanned_landing(
+        board: &Board,
+        player: Player,
+        to: PiecePosition,
+        rules: &RuleSet,
+    ) -> Landing {
+        let geometry = rules.geometry;
+        if to.is_last(geometry) {
+            return Landing::Free;
+        }
+        let new_pos = to.get_coords(player, geometry);
+        for &occupant in &rules.seats {
+            let pieces = board.players[occupant].pieces_positions;
+            let Some(piece) = (0..4u8).find(|&piece| {
+                let pos = pieces[piece as usize];
+                pos != 0 && pos.get_coords(occupant, geometry) == new_pos
+            }) else {
+                continue;
+            };
+            return if occupant.is_enemy_of(player, rules)
+                && !new_pos.is_safe(rules)
+                && 1.get_coords(occupant, geometry) != new_pos
+            {
+                Landing::Capture(occupant, piece)
+            } else {
+                Landing::Blocked(occupant)
+            };
+        }
+        Landing::Free
+    }
 
     #[test]
     fn test_initial() {
+        let rules = RuleSet::classic();
         let mut board = Board::default();
-        let actions = board.actions_for_player(3, Player::First);
+        let actions = board.actions_for_player(3, Player::First, &rules);
         assert_eq!(actions.len(), 0);
 
-        let actions = board.actions_for_player(1, Player::First);
+        let actions = board.actions_for_player(1, Player::First, &rules);
         assert_eq!(actions.len(), 2);
-        let actions = board.actions_for_player(6, Player::First);
+        let actions = board.actions_for_player(6, Player::First, &rules);
         assert_eq!(actions.len(), 2);
 
-        board.apply_action(Action {
+        board
+            .apply_action(
+                Action {
+                    player: Player::First,
+                    piece: 0,
+                    from: 0,
+                    to: 1,
+                },
+                &rules,
+            )
+            .unwrap();
+
+        let actions = board.actions_for_player(2, Player::First, &rules);
+        assert_eq!(actions.len(), 1);
+    }
+
+    #[test]
+    fn test_house_rules() {
+        let rules = RuleSet {
+            entry_rolls: vec![6],
+            capture_bonus: false,
+            team_mode: false,
+            ..RuleSet::classic()
+        };
+        let mut board = Board::default();
+        assert_eq!(board.actions_for_player(1, Player::First, &rules).len(), 0);
+        // Only our own pieces can be brought in without a team.
+        assert_eq!(board.actions_for_player(6, Player::First, &rules).len(), 1);
+
+        board.players[Player::First].pieces_positions[0] = 6;
+        board.players[Player::Third].pieces_positions[0] = 25;
+        let capture = Action {
             player: Player::First,
             piece: 0,
-            from: 0,
-            to: 1,
-        });
+            from: 6,
+            to: 7,
+        };
+        // Without teams, the opposite seat's pieces can be captured.
+        assert!(board
+            .actions_for_player(1, Player::First, &rules)
+            .contains(&capture));
+        let outcome = board.apply_action(capture, &rules).unwrap();
+        assert_eq!(outcome.captured, Some((Player::Third, 0)));
+        assert!(!outcome.another_turn);
+        assert_eq!(board.players[Player::Third].pieces_positions[0], 0);
+        // Stale actions are rejected instead of teleporting the piece.
+        assert_eq!(
+            board.apply_action(capture, &rules),
+            Err(IllegalMove::StaleFrom {
+                expected: 6,
+                found: 7
+            })
+        );
+    }
 
-        let actions = board.actions_for_player(2, Player::First);
-        assert_eq!(actions.len(), 1);
+    #[test]
+    fn test_generator() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let hexagonal_teams = RuleSet {
+            team_mode: true,
+            ..RuleSet::six_player()
+        };
+        for rules in [RuleSet::classic(), RuleSet::three_player(), hexagonal_teams] {
+            let mut state = GameState::with_rules(Board::default(), Player::First, rules);
+            let rules = state.rules.clone();
+            while state.winner().is_none() && !state.is_over() {
+                let dice = rng.gen_range(1..=6);
+                // Every piece that can legally move by the roll, one per
+                // starting place.
+                let mut legal = vec![];
+                for &player in &rules.seats {
+                    for piece in 0..4 {
+                        let from = state.board().players[player][piece];
+                        let to = if from == 0 { 1 } else { from + dice };
+                        if to <= rules.geometry.last_place() {
+                            assert_eq!(
+                                state.occupancy().landing(state.board(), player, to, &rules),
+                                scanned_landing(state.board(), player, to, &rules)
+                            );
+                        }
+                        let action = Action {
+                            player,
+                            piece,
+                            from,
+                            to,
+                        };
+                        if !legal.contains(&action)
+                            && state.clone().apply_action(dice, action).is_ok()
+                        {
+                            legal.push(action);
+                        }
+                    }
+                }
+                let fields =
+                    |action: &Action| (action.player, action.piece, action.from, action.to);
+                let actions = state.get_actions(dice);
+                assert!(actions.iter().map(fields).eq(legal.iter().map(fields)));
+                state
+                    .roll(dice, |_, actions| rng.gen_range(0..actions.len()))
+                    .unwrap();
+                // Kept up to date as the moves are played.
+                if let Some(occupancy) = state.occupancy {
+                    assert!(occupancy.fits(&rules));
+                    let built = Occupancy::new(state.board(), &rules);
+                    assert_eq!(occupancy.squares, built.squares);
+                    assert_eq!(occupancy.key(), built.key());
+                }
+            }
+        }
     }
 }
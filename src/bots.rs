@@ -1,44 +1,181 @@
-use crate::board::{Action, PieceLocationExt, PiecePosition, PiecePositionExt};
+use crate::board::{Action, Board, Occupancy, PieceLocationExt, PiecePosition, PiecePositionExt};
 use crate::game_state::GameState;
+use crate::history::Ply;
+use crate::rules::RuleSet;
 use crate::Player;
+use enum_map::{enum_map, EnumMap};
 use itertools::Itertools;
-use rand::{thread_rng, Rng};
+use rand::{Rng, RngCore};
+use std::ops::{Index, IndexMut};
 
-//pub trait Bot = Fn(&GameState, &Vec<Action>) -> usize;
-pub type Bot = fn(&GameState, &Vec<Action>) -> usize;
+/// A player that picks its moves, possibly keeping state between them.
+///
+/// Simulations give every game its own copy of each bot, made with
+/// `box_clone`, and tell it how the game goes through the hooks. Plain
+/// functions taking the state, the actions and an rng are bots too.
+pub trait Bot: Send + Sync {
+    /// Picks one of the actions the player to move in `state` can play
+    /// after rolling `dice`, drawing any randomness it needs from the rng so
+    /// that games can be reproduced.
+    fn choose(
+        &mut self,
+        state: &GameState,
+        dice: u8,
+        actions: &[Action],
+        rng: &mut dyn RngCore,
+    ) -> usize;
 
-pub fn choose_closest_to_target(_state: &GameState, actions: &Vec<Action>) -> usize {
+    /// Called before the first move of a game starting from `state`.
+    fn new_game(&mut self, _state: &GameState) {}
+
+    /// Called after every ply, whoever played it, with the state it led to.
+    fn observe_move(&mut self, _ply: &Ply, _state: &GameState) {}
+
+    /// Called with the final state once the game is over.
+    fn game_over(&mut self, _state: &GameState) {}
+
+    fn box_clone(&self) -> Box<dyn Bot>;
+
+    /// Name for records and tournament tables.
+    fn name(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+}
+
+impl<F> Bot for F
+where
+    F: Fn(&GameState, &[Action], &mut dyn RngCore) -> usize + Clone + Send + Sync + 'static,
+{
+    fn choose(
+        &mut self,
+        state: &GameState,
+        _dice: u8,
+        actions: &[Action],
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        self(state, actions, rng)
+    }
+
+    fn box_clone(&self) -> Box<dyn Bot> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Bot> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// The bot playing each seat.
+///
+/// Only the seats of the game being played need a bot: asking for the bot
+/// of an empty seat panics.
+#[derive(Clone, Default)]
+pub struct Bots(EnumMap<Player, Option<Box<dyn Bot>>>);
+
+impl Bots {
+    /// A bot for each seat `rules` has, made by `bot`.
+    pub fn seated(rules: &RuleSet, mut bot: impl FnMut(Player) -> Box<dyn Bot>) -> Self {
+        rules
+            .seats
+            .iter()
+            .map(|&player| (player, bot(player)))
+            .collect()
+    }
+}
+
+impl FromIterator<(Player, Box<dyn Bot>)> for Bots {
+    fn from_iter<I: IntoIterator<Item = (Player, Box<dyn Bot>)>>(iter: I) -> Self {
+        let mut bots = Self::default();
+        for (player, bot) in iter {
+            bots.0[player] = Some(bot);
+        }
+        bots
+    }
+}
+
+impl Index<Player> for Bots {
+    type Output = Box<dyn Bot>;
+
+    fn index(&self, player: Player) -> &Self::Output {
+        self.0[player]
+            .as_ref()
+            .unwrap_or_else(|| panic!("no bot plays {player:?}"))
+    }
+}
+
+impl IndexMut<Player> for Bots {
+    fn index_mut(&mut self, player: Player) -> &mut Self::Output {
+        self.0[player]
+            .as_mut()
+            .unwrap_or_else(|| panic!("no bot plays {player:?}"))
+    }
+}
+
+/// The same bot on every seat, each with its own copy, whatever the rules.
+pub fn all_seats(bot: impl Bot + 'static) -> Bots {
+    Bots(enum_map! {_ => Some(bot.box_clone())})
+}
+
+pub fn choose_closest_to_target(
+    _state: &GameState,
+    actions: &[Action],
+    _rng: &mut dyn RngCore,
+) -> usize {
     actions
         .iter()
         .position_max_by_key(|action| action.to)
         .unwrap()
 }
 
-pub fn choose_random(_state: &GameState, actions: &Vec<Action>) -> usize {
-    thread_rng().gen_range(0..actions.len())
+pub fn choose_random(_state: &GameState, actions: &[Action], rng: &mut dyn RngCore) -> usize {
+    rng.gen_range(0..actions.len())
+}
+
+/// How many dice values let some enemy land on the piece `player` has at
+/// `position`, summed over the enemies. Pieces on safe spots or their own
+/// start square can't be captured.
+///
+/// `occupancy` has to describe `board`.
+pub fn piece_risk(
+    board: &Board,
+    occupancy: &Occupancy,
+    rules: &RuleSet,
+    player: Player,
+    position: PiecePosition,
+) -> usize {
+    let pos = position.get_coords(player, rules.geometry);
+    if pos.is_safe(rules) || position.is_home() {
+        return 0;
+    }
+    player
+        .enemies(rules)
+        .into_iter()
+        .map(|enemy| {
+            (1u8..=6)
+                .filter(|&dice| {
+                    board
+                        .actions_with(occupancy, dice, enemy, rules)
+                        .iter()
+                        .any(|a| a.to.get_coords(enemy, rules.geometry) == pos)
+                })
+                .count()
+        })
+        .sum::<usize>()
 }
 
-pub fn average_bot(state: &GameState, actions: &Vec<Action>) -> usize {
+pub fn average_bot(state: &GameState, actions: &[Action], _rng: &mut dyn RngCore) -> usize {
+    let occupancy = state.occupancy();
     let piece_risk = |player: Player, position_index: PiecePosition| {
-        let pos = position_index.get_coords(player);
-        if pos.is_safe() || position_index.is_home() {
-            return 0;
-        }
-        player
-            .enemies()
-            .map(|enemy| {
-                (1u8..=6)
-                    .filter(|&dice| {
-                        state
-                            .board
-                            .actions_for_player(dice, enemy)
-                            .iter()
-                            .any(|a| a.to.get_coords(enemy) == pos)
-                    })
-                    .count()
-            })
-            .iter()
-            .sum()
+        piece_risk(
+            state.board(),
+            &occupancy,
+            &state.rules,
+            player,
+            position_index,
+        )
     };
 
     let risk_delta = |action: &Action| {
@@ -50,7 +187,7 @@ pub fn average_bot(state: &GameState, actions: &Vec<Action>) -> usize {
     // Find win action that had the most risk.
     let win_action = actions
         .iter()
-        .filter(|action| action.to.is_last())
+        .filter(|action| action.to.is_last(state.rules.geometry))
         .max_by_key(|action| piece_risk(action.player, action.from));
 
     if let Some(action) = win_action {
@@ -61,15 +198,15 @@ pub fn average_bot(state: &GameState, actions: &Vec<Action>) -> usize {
     let eat_action = actions
         .iter()
         .flat_map(|action| {
-            let pos = action.to.get_coords(action.player);
+            let pos = action.to.get_coords(action.player, state.rules.geometry);
             action
                 .player
-                .enemies()
-                .iter()
-                .flat_map(|&enemy| {
-                    state.board.players[enemy]
+                .enemies(&state.rules)
+                .into_iter()
+                .flat_map(|enemy| {
+                    state.board().players[enemy]
                         .pieces_positions
-                        .map(|index| (index, index.get_coords(enemy)))
+                        .map(|index| (index, index.get_coords(enemy, state.rules.geometry)))
                 })
                 .filter_map(|(index, enemy_pos)| {
                     if enemy_pos == pos {
@@ -84,7 +221,7 @@ pub fn average_bot(state: &GameState, actions: &Vec<Action>) -> usize {
         .map(|(action, _)| action);
 
     if let Some(eat_action) = eat_action {
-        return action_index(&eat_action);
+        return action_index(eat_action);
     }
 
     // Move closest to target with minimum risk
@@ -0,0 +1,239 @@
+//! Where dice rolls come from.
+//!
+//! Simulations draw their dice, and the bots their random choices, from a
+//! `DiceSource`, so that a game can be replayed exactly from a seed, from a
+//! script, or played with real dice.
+
+use std::io::{BufRead, StdinLock, Stdout, Write};
+
+use enum_map::EnumMap;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use crate::utils::roll_dice;
+use crate::Player;
+
+pub trait DiceSource {
+    /// Next roll, between `1` and `6`, or `None` if there are no more.
+    fn roll(&mut self) -> Option<u8>;
+
+    /// Next roll for `player`, for sources that keep each seat's rolls
+    /// apart. The same as `roll` unless the source does.
+    fn roll_for(&mut self, _player: Player) -> Option<u8> {
+        self.roll()
+    }
+
+    /// Randomness for the choices made alongside these rolls, such as the
+    /// bots' moves.
+    fn rng(&mut self) -> &mut dyn RngCore;
+}
+
+/// Derives an independent seed for stream `stream` of a run seeded with
+/// `seed`, such as one game out of many played in parallel.
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    // SplitMix64, so nearby streams get unrelated seeds.
+    let mut z = seed ^ stream.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Dice from a pseudo-random generator, giving the same rolls for the same
+/// seed.
+pub struct SeededDice(StdRng);
+
+impl SeededDice {
+    pub fn new(seed: u64) -> Self {
+        Self(StdRng::seed_from_u64(seed))
+    }
+
+    /// Dice seeded from the operating system, for when the game doesn't
+    /// need to be reproduced.
+    pub fn from_entropy() -> Self {
+        Self(StdRng::from_entropy())
+    }
+}
+
+impl DiceSource for SeededDice {
+    fn roll(&mut self) -> Option<u8> {
+        Some(roll_dice(&mut self.0))
+    }
+
+    fn rng(&mut self) -> &mut dyn RngCore {
+        &mut self.0
+    }
+}
+
+/// Dice with the rolls and the bots' choices drawn from separate seeds, and
+/// each seat's rolls apart, so that games which go differently still give
+/// every player the same rolls in the same order.
+pub struct SplitDice {
+    rolls: EnumMap<Player, StdRng>,
+    choices: StdRng,
+    mirrored: bool,
+}
+
+impl SplitDice {
+    pub fn new(rolls_seed: u64, choices_seed: u64) -> Self {
+        Self {
+            rolls: EnumMap::from_array(
+                [0, 1, 2, 3, 4, 5].map(|seat| StdRng::seed_from_u64(derive_seed(rolls_seed, seat))),
+            ),
+            choices: StdRng::seed_from_u64(choices_seed),
+            mirrored: false,
+        }
+    }
+
+    /// The rolls of `new` with the same seed turned upside down, so each
+    /// `d` becomes `7 - d`: just as likely, but as far from the original as
+    /// it gets.
+    pub fn mirrored(rolls_seed: u64, choices_seed: u64) -> Self {
+        Self {
+            mirrored: true,
+            ..Self::new(rolls_seed, choices_seed)
+        }
+    }
+}
+
+impl DiceSource for SplitDice {
+    /// Rolls from the first seat's rolls.
+    fn roll(&mut self) -> Option<u8> {
+        self.roll_for(Player::First)
+    }
+
+    fn roll_for(&mut self, player: Player) -> Option<u8> {
+        let dice = roll_dice(&mut self.rolls[player]);
+        Some(if self.mirrored { 7 - dice } else { dice })
+    }
+
+    fn rng(&mut self) -> &mut dyn RngCore {
+        &mut self.choices
+    }
+}
+
+/// A fixed list of rolls, such as the ones from a bug report or a recorded
+/// game.
+pub struct ScriptedDice {
+    rolls: Vec<u8>,
+    next: usize,
+    rng: StdRng,
+}
+
+impl ScriptedDice {
+    pub fn new(rolls: impl IntoIterator<Item = u8>) -> Self {
+        Self::with_seed(rolls, 0)
+    }
+
+    /// Scripted rolls, with the bots' choices seeded with `seed`.
+    pub fn with_seed(rolls: impl IntoIterator<Item = u8>, seed: u64) -> Self {
+        Self {
+            rolls: rolls.into_iter().collect(),
+            next: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Rolls not yet used.
+    pub fn remaining(&self) -> &[u8] {
+        &self.rolls[self.next..]
+    }
+}
+
+impl DiceSource for ScriptedDice {
+    fn roll(&mut self) -> Option<u8> {
+        let dice = *self.rolls.get(self.next)?;
+        self.next += 1;
+        Some(dice)
+    }
+
+    fn rng(&mut self) -> &mut dyn RngCore {
+        &mut self.rng
+    }
+}
+
+/// Real dice, with each roll typed in by whoever threw them.
+pub struct PhysicalDice<R, W> {
+    input: R,
+    prompt: W,
+    rng: StdRng,
+}
+
+impl PhysicalDice<StdinLock<'static>, Stdout> {
+    pub fn stdin() -> Self {
+        Self::new(std::io::stdin().lock(), std::io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> PhysicalDice<R, W> {
+    /// Reads rolls from `input`, one per line, asking for them on `prompt`.
+    pub fn new(input: R, prompt: W) -> Self {
+        Self {
+            input,
+            prompt,
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> DiceSource for PhysicalDice<R, W> {
+    /// Asks until a roll between `1` and `6` is given, returning `None`
+    /// once the input ends.
+    fn roll(&mut self) -> Option<u8> {
+        let mut line = String::new();
+        loop {
+            write!(self.prompt, "Dice: ").ok()?;
+            self.prompt.flush().ok()?;
+            line.clear();
+            if self.input.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            match line.trim().parse() {
+                Ok(dice @ 1..=6) => return Some(dice),
+                _ => writeln!(self.prompt, "Expected a number from 1 to 6").ok()?,
+            }
+        }
+    }
+
+    fn rng(&mut self) -> &mut dyn RngCore {
+        &mut self.rng
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources() {
+        let rolls =
+            |dice: &mut dyn DiceSource| (0..20).map(|_| dice.roll().unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            rolls(&mut SeededDice::new(7)),
+            rolls(&mut SeededDice::new(7))
+        );
+        assert_ne!(
+            rolls(&mut SeededDice::new(7)),
+            rolls(&mut SeededDice::new(8))
+        );
+        assert_ne!(derive_seed(7, 0), derive_seed(7, 1));
+
+        let mut split = SplitDice::new(7, 1);
+        let mut mirrored = SplitDice::mirrored(7, 2);
+        mirrored.rng().next_u64();
+        for _ in 0..20 {
+            assert_eq!(split.roll().unwrap() + mirrored.roll().unwrap(), 7);
+        }
+
+        let mut scripted = ScriptedDice::new([6, 1]);
+        assert_eq!(scripted.roll(), Some(6));
+        assert_eq!(scripted.remaining(), [1]);
+        assert_eq!(scripted.roll(), Some(1));
+        assert_eq!(scripted.roll(), None);
+
+        let mut prompt = vec![];
+        let mut physical = PhysicalDice::new("7\nsix\n4\n".as_bytes(), &mut prompt);
+        assert_eq!(physical.roll(), Some(4));
+        assert_eq!(physical.roll(), None);
+        assert!(String::from_utf8(prompt).unwrap().contains("from 1 to 6"));
+    }
+}
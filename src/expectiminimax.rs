@@ -0,0 +1,374 @@
+//! Expectiminimax bot.
+//!
+//! Searches a fixed number of plies, averaging over the six rolls at
+//! chance nodes. Seats on the side of the player to move at the root
+//! maximise, and everyone else minimises. Chance nodes can be pruned with
+//! Ballard's Star1, or Star2, which first probes one move per roll to bound
+//! the average.
+
+use std::sync::Arc;
+
+use itertools::Itertools;
+use rand::RngCore;
+
+use crate::board::{Action, Side};
+use crate::bots::Bot;
+use crate::game_state::GameState;
+
+/// Evaluations lie between these, with a lost game at `LOSS` and a won one
+/// at `WIN`.
+pub const LOSS: f64 = -1.0;
+pub const WIN: f64 = 1.0;
+
+/// Scores states that the search doesn't look past.
+pub trait Evaluator: Send + Sync {
+    /// How good `state` is for `side`, strictly between `LOSS` and `WIN`.
+    fn evaluate(&self, state: &GameState, side: Side) -> f64;
+}
+
+impl<F> Evaluator for F
+where
+    F: Fn(&GameState, Side) -> f64 + Send + Sync,
+{
+    fn evaluate(&self, state: &GameState, side: Side) -> f64 {
+        self(state, side)
+    }
+}
+
+/// How far the side's pieces are along their tracks on average, minus how
+/// far everyone else's are.
+pub fn progress(state: &GameState, side: Side) -> f64 {
+    let last_place = state.rules.geometry.last_place() as f64;
+    let (mut ours, mut theirs) = (vec![], vec![]);
+    for &player in &state.rules.seats {
+        let pieces = state.board().players[player].pieces_positions;
+        let progress = pieces.iter().map(|&pos| pos as f64).sum::<f64>() / (4.0 * last_place);
+        if side.contains(player) {
+            ours.push(progress);
+        } else {
+            theirs.push(progress);
+        }
+    }
+    let mean = |values: Vec<f64>| values.iter().sum::<f64>() / values.len().max(1) as f64;
+    // Kept off the bounds, which are for games that are over.
+    (mean(ours) - mean(theirs)) * 0.99
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pruning {
+    /// Plain expectiminimax, visiting every node.
+    None,
+    /// Alpha-beta at decision nodes, and Star1 at chance nodes.
+    Star1,
+    /// Like `Star1`, probing one move per roll before the full search.
+    Star2,
+}
+
+#[derive(Clone)]
+pub struct ExpectiminimaxConfig {
+    /// Moves to look ahead, counting every player's.
+    pub depth: u32,
+    pub pruning: Pruning,
+    pub evaluator: Arc<dyn Evaluator>,
+}
+
+impl Default for ExpectiminimaxConfig {
+    fn default() -> Self {
+        Self {
+            depth: 2,
+            pruning: Pruning::Star2,
+            evaluator: Arc::new(progress),
+        }
+    }
+}
+
+/// Which moves a decision node searches.
+#[derive(Clone, Copy)]
+enum Moves {
+    /// Only the first, which bounds the node's value.
+    Probe,
+    /// All of them, given the first one's value if it's known.
+    All(Option<f64>),
+}
+
+struct Search<'a> {
+    config: &'a ExpectiminimaxConfig,
+    side: Side,
+    nodes: u64,
+}
+
+impl Search<'_> {
+    fn evaluate(&self, state: &GameState) -> f64 {
+        self.config
+            .evaluator
+            .evaluate(state, self.side)
+            .clamp(LOSS, WIN)
+    }
+
+    fn maximizing(&self, state: &GameState) -> bool {
+        self.side.contains(state.turn)
+    }
+
+    /// States after each move `dice` allows, best first for the player to
+    /// move, or after passing if there are none.
+    fn children(
+        &self,
+        state: &GameState,
+        dice: u8,
+        depth: u32,
+    ) -> Vec<(Option<Action>, GameState)> {
+        let actions = state.get_actions(dice);
+        if actions.is_empty() {
+            let mut next = state.clone();
+            next.roll(dice, |_, _| 0).expect("passing is legal");
+            return vec![(None, next)];
+        }
+        let children = actions
+            .into_iter()
+            .sorted_by_key(|action| (action.player as usize, action.piece))
+            .map(|action| {
+                let mut next = state.clone();
+                next.apply_action(dice, action)
+                    .expect("actions from get_actions are legal");
+                (Some(action), next)
+            });
+        if depth <= 1 {
+            return children.collect();
+        }
+        // Searching the best looking moves first prunes more.
+        let sign = if self.maximizing(state) { -1.0 } else { 1.0 };
+        children
+            .map(|(action, next)| (sign * self.evaluate(&next), action, next))
+            .sorted_by(|(a, ..), (b, ..)| a.total_cmp(b))
+            .map(|(_, action, next)| (action, next))
+            .collect()
+    }
+
+    /// Value of a state waiting for a roll, exact if it's inside
+    /// `(alpha, beta)` and otherwise a bound on the side it fell.
+    fn chance(&mut self, state: &GameState, depth: u32, alpha: f64, beta: f64) -> f64 {
+        self.nodes += 1;
+        if let Some(winner) = state.winner() {
+            return if winner == self.side { WIN } else { LOSS };
+        }
+        if depth == 0 {
+            return self.evaluate(state);
+        }
+        if self.config.pruning == Pruning::None {
+            return (1..=6)
+                .map(|dice| self.decision(state, dice, depth, LOSS, WIN, Moves::All(None)))
+                .sum::<f64>()
+                / 6.0;
+        }
+
+        // Bounds on the value after each roll.
+        let mut lower = [LOSS; 6];
+        let mut upper = [WIN; 6];
+        // Exact values of the moves probed, so they aren't searched again.
+        let mut probed = [None; 6];
+        if self.config.pruning == Pruning::Star2 {
+            // Any one move bounds what the player to move can get. Each
+            // probe only needs to be exact past the value that could still
+            // cut the average off.
+            let maximizing = self.maximizing(state);
+            for i in 0..6 {
+                let dice = i as u8 + 1;
+                let rest = (5 - i) as f64;
+                if maximizing {
+                    let needed = 6.0 * beta - lower[..i].iter().sum::<f64>() - rest * WIN;
+                    if needed >= WIN {
+                        continue;
+                    }
+                    let probe =
+                        self.decision(state, dice, depth, needed.max(LOSS), WIN, Moves::Probe);
+                    if probe > needed {
+                        lower[i] = probe;
+                        probed[i] = Some(probe);
+                    }
+                } else {
+                    let needed = 6.0 * alpha - upper[..i].iter().sum::<f64>() - rest * LOSS;
+                    if needed <= LOSS {
+                        continue;
+                    }
+                    let probe =
+                        self.decision(state, dice, depth, LOSS, needed.min(WIN), Moves::Probe);
+                    if probe < needed {
+                        upper[i] = probe;
+                        probed[i] = Some(probe);
+                    }
+                }
+            }
+            if lower.iter().sum::<f64>() / 6.0 >= beta {
+                return beta;
+            }
+            if upper.iter().sum::<f64>() / 6.0 <= alpha {
+                return alpha;
+            }
+        }
+
+        let mut sum = 0.0;
+        for i in 0..6 {
+            // This roll's value must clear these for the average to stay
+            // inside the window, given the rolls still to search.
+            let low = 6.0 * alpha - sum - upper[i + 1..].iter().sum::<f64>();
+            let high = 6.0 * beta - sum - lower[i + 1..].iter().sum::<f64>();
+            if upper[i] <= low {
+                return alpha;
+            }
+            if lower[i] >= high {
+                return beta;
+            }
+            let value = self.decision(
+                state,
+                i as u8 + 1,
+                depth,
+                low.max(lower[i]),
+                high.min(upper[i]),
+                Moves::All(probed[i]),
+            );
+            if value <= low {
+                return alpha;
+            }
+            if value >= high {
+                return beta;
+            }
+            sum += value;
+        }
+        sum / 6.0
+    }
+
+    /// Value of the best move after rolling `dice`, or of the first one
+    /// when probing.
+    fn decision(
+        &mut self,
+        state: &GameState,
+        dice: u8,
+        depth: u32,
+        mut alpha: f64,
+        mut beta: f64,
+        moves: Moves,
+    ) -> f64 {
+        self.nodes += 1;
+        let children = self.children(state, dice, depth);
+        let first = match moves {
+            Moves::Probe => return self.chance(&children[0].1, depth - 1, alpha, beta),
+            Moves::All(first) => first,
+        };
+        let maximizing = self.maximizing(state);
+        let mut best = if maximizing { LOSS } else { WIN };
+        for (i, (_, child)) in children.iter().enumerate() {
+            let value = match first {
+                Some(value) if i == 0 => value,
+                _ => self.chance(child, depth - 1, alpha, beta),
+            };
+            if maximizing {
+                best = best.max(value);
+                alpha = alpha.max(value);
+            } else {
+                best = best.min(value);
+                beta = beta.min(value);
+            }
+            if self.config.pruning != Pruning::None && alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ExpectiminimaxBot {
+    pub config: ExpectiminimaxConfig,
+    nodes: u64,
+}
+
+impl ExpectiminimaxBot {
+    pub fn new(config: ExpectiminimaxConfig) -> Self {
+        Self { config, nodes: 0 }
+    }
+
+    /// Nodes visited by the last search.
+    pub fn nodes_searched(&self) -> u64 {
+        self.nodes
+    }
+
+    /// Best move for the player to move after rolling `dice`, and its
+    /// value, if there are any moves.
+    pub fn search(&mut self, state: &GameState, dice: u8) -> Option<(Action, f64)> {
+        let mut search = Search {
+            config: &self.config,
+            side: state.turn.side(&state.rules),
+            nodes: 0,
+        };
+        let depth = self.config.depth.max(1);
+        let mut best: Option<(Action, f64)> = None;
+        for (action, child) in search.children(state, dice, depth) {
+            let alpha = match (best, self.config.pruning) {
+                (Some((_, value)), Pruning::Star1 | Pruning::Star2) => value,
+                _ => LOSS,
+            };
+            let value = search.chance(&child, depth - 1, alpha, WIN);
+            if best.is_none_or(|(_, best)| value > best) {
+                best = action.map(|action| (action, value));
+            }
+        }
+        self.nodes = search.nodes;
+        best
+    }
+}
+
+impl Bot for ExpectiminimaxBot {
+    fn choose(
+        &mut self,
+        state: &GameState,
+        dice: u8,
+        actions: &[Action],
+        _rng: &mut dyn RngCore,
+    ) -> usize {
+        if actions.len() == 1 {
+            return 0;
+        }
+        let (best, _) = self
+            .search(state, dice)
+            .expect("there are actions to choose from");
+        actions
+            .iter()
+            .position(|action| action.piece == best.piece && action == &best)
+            .expect("the search has the same actions")
+    }
+
+    fn box_clone(&self) -> Box<dyn Bot> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Player;
+
+    #[test]
+    fn test_pruning() {
+        let mut state = GameState::default();
+        state.board_mut().players[Player::First].pieces_positions = [5, 14, 0, 0];
+        state.board_mut().players[Player::Second].pieces_positions = [3, 0, 0, 0];
+        state.board_mut().players[Player::Third].pieces_positions = [22, 0, 0, 0];
+        state.board_mut().players[Player::Fourth].pieces_positions = [8, 30, 0, 0];
+
+        let mut searches = [Pruning::None, Pruning::Star1, Pruning::Star2].map(|pruning| {
+            let mut bot = ExpectiminimaxBot::new(ExpectiminimaxConfig {
+                depth: 3,
+                pruning,
+                ..Default::default()
+            });
+            let (action, value) = bot.search(&state, 6).unwrap();
+            (action, action.piece, value, bot.nodes_searched())
+        });
+        let (action, piece, value, nodes) = searches[0];
+        for (other_action, other_piece, other_value, other_nodes) in &mut searches[1..] {
+            assert_eq!((*other_action, *other_piece), (action, piece));
+            assert!((*other_value - value).abs() < 1e-9);
+            assert!(*other_nodes < nodes);
+        }
+    }
+}
@@ -1,87 +1,620 @@
-use enum_map::enum_map;
+use enum_map::{enum_map, EnumMap};
 use itertools::Itertools;
-use std::{collections::HashSet, fmt::Display};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, collections::HashMap, fmt::Display, iter, sync::Arc};
 
 use crate::{
-    board::{Action, Board},
-    positions::{LAST_PLACE, POSITIONS, SAFE_SPOTS},
-    Player, NEXT_PLAYER,
+    board::{
+        Action, Actions, Board, IllegalMove, MoveOutcome, Occupancy, PieceLocation, PiecePosition,
+        PiecePositionExt, PlayerData, Side,
+    },
+    positions::{Geometry, LAST_PLACE},
+    rules::RuleSet,
+    zobrist, Player,
 };
 use colored::{Color, Colorize};
 
-#[derive(Clone, Copy, serde::Serialize, Default)]
+/// Serialised with only the seated players' pieces, and checked with
+/// `validate` when deserialised.
+#[derive(Clone, Serialize, Deserialize, Default)]
+#[serde(into = "SavedState", try_from = "SavedState")]
 pub struct GameState {
-    pub board: Board,
+    board: Board,
     pub turn: Player,
+    pub rules: Arc<RuleSet>,
     six_rolled: u8,
+    pub(crate) finishing_order: Vec<Player>,
+    /// Occupancy and key of the board, kept up to date by the moves played
+    /// and dropped by `board_mut`.
+    pub(crate) occupancy: Option<Occupancy>,
+}
+
+/// Turns down rules seating a player off the board up front, as they'd
+/// otherwise only fail deep in move generation.
+fn assert_seats(rules: &RuleSet) {
+    if let Some(player) = rules.unknown_seat() {
+        panic!("{player:?} has no seat on the {:?} board", rules.geometry);
+    }
 }
 
 impl GameState {
     pub fn new(board: Board, first_player: Player) -> Self {
-        Self {
+        Self::with_rules(board, first_player, RuleSet::classic())
+    }
+
+    /// A game played by `rules`, starting with `first_player`, or the next
+    /// seated player if that seat is empty.
+    ///
+    /// Panics if `rules` seats a player the board has no seat for.
+    pub fn with_rules(board: Board, first_player: Player, rules: RuleSet) -> Self {
+        assert_seats(&rules);
+        let turn = if rules.is_seated(first_player) {
+            first_player
+        } else {
+            first_player.next(&rules)
+        };
+        let mut state = Self {
             board,
-            turn: first_player,
+            turn,
+            occupancy: Some(Occupancy::new(&board, &rules)),
+            rules: Arc::new(rules),
             six_rolled: 0,
+            finishing_order: vec![],
+        };
+        state.record_finishers();
+        state
+    }
+
+    /// Builds a state as is, working the finishing order out from the
+    /// board when not given.
+    ///
+    /// Panics if `rules` seats a player the board has no seat for.
+    pub(crate) fn from_parts(
+        board: Board,
+        turn: Player,
+        rules: RuleSet,
+        six_rolled: u8,
+        finishing_order: Option<Vec<Player>>,
+    ) -> Self {
+        assert_seats(&rules);
+        let mut state = Self {
+            board,
+            turn,
+            occupancy: Some(Occupancy::new(&board, &rules)),
+            rules: Arc::new(rules),
+            six_rolled,
+            finishing_order: finishing_order.clone().unwrap_or_default(),
+        };
+        if finishing_order.is_none() {
+            state.record_finishers();
         }
+        state
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
     }
 
-    pub fn get_actions(&self, dice: u8) -> HashSet<Action> {
-        if dice == 6 && self.six_rolled > 1 {
-            return HashSet::new();
+    /// The board, to be changed by hand. The occupancy worked out from it
+    /// is dropped, to be rebuilt the next time it's needed.
+    pub fn board_mut(&mut self) -> &mut Board {
+        self.occupancy = None;
+        &mut self.board
+    }
+
+    /// Sixes rolled in a row by the player to move.
+    pub fn six_rolled(&self) -> u8 {
+        self.six_rolled
+    }
+
+    /// Zobrist key of the pieces, the player to move, the sixes they rolled
+    /// and the finishing order: equal for states that play out the same, and
+    /// almost never for others.
+    ///
+    /// The board's part is worked out when the state is built and kept up
+    /// to date as actions are applied. It's only hashed afresh, on every
+    /// call, for states that were loaded or had their board changed through
+    /// `board_mut`, until their next action. The rest is a few lookups
+    /// recomputed on each call, as `turn` can be changed directly.
+    pub fn key(&self) -> u64 {
+        let board = match &self.occupancy {
+            Some(occupancy) if occupancy.fits(&self.rules) => occupancy.key(),
+            _ => self.board.key(),
+        };
+        self.finishing_order.iter().enumerate().fold(
+            board ^ zobrist::turn(self.turn) ^ zobrist::sixes(self.six_rolled),
+            |key, (place, &player)| key ^ zobrist::finisher(place, player),
+        )
+    }
+
+    /// Checks this state could have come up in a game played under its rules.
+    pub fn validate(&self) -> Result<(), InvalidState> {
+        let rules = &self.rules;
+        let geometry = rules.geometry;
+        if let Some(player) = rules.unknown_seat() {
+            return Err(InvalidState::UnknownSeat(player));
         }
-        self.board.actions_for_player(dice, self.turn)
+        if !rules.is_seated(self.turn) {
+            return Err(InvalidState::EmptySeatToMove(self.turn));
+        }
+        if rules.max_consecutive_sixes != 0 && self.six_rolled >= rules.max_consecutive_sixes {
+            return Err(InvalidState::SixStreak(self.six_rolled));
+        }
+
+        let mut occupied = HashMap::new();
+        for &player in &rules.seats {
+            for (piece, &position) in self.board.players[player]
+                .pieces_positions
+                .iter()
+                .enumerate()
+            {
+                if position > geometry.last_place() {
+                    return Err(InvalidState::PositionOutOfRange {
+                        player,
+                        piece: piece as u8,
+                        position,
+                    });
+                }
+                if position.is_first() || position.is_last(geometry) {
+                    continue;
+                }
+                let location = position.get_coords(player, geometry);
+                if let Some(other) = occupied.insert(location, player) {
+                    return Err(InvalidState::SharedSquare {
+                        location,
+                        players: (other, player),
+                    });
+                }
+            }
+        }
+
+        let complete = !rules.team_mode && self.finishing_order.len() == rules.seats.len();
+        for (i, &player) in self.finishing_order.iter().enumerate() {
+            let last_left = complete && i == self.finishing_order.len() - 1;
+            if !rules.is_seated(player)
+                || self.finishing_order[..i].contains(&player)
+                || !(last_left || self.board.has_finished(player, rules))
+            {
+                return Err(InvalidState::FinishingOrder(player));
+            }
+        }
+        if let Some(&player) = rules.seats.iter().find(|&&player| {
+            self.board.has_finished(player, rules) && !self.finishing_order.contains(&player)
+        }) {
+            return Err(InvalidState::FinishingOrder(player));
+        }
+
+        Ok(())
     }
 
-    pub fn roll(
-        &mut self,
-        dice: u8,
-        action_chooser: impl FnOnce(&GameState, &Vec<Action>) -> usize,
-    ) {
-        let actions = self.get_actions(dice).iter().cloned().collect_vec();
+    /// Actions the player to move can play after rolling `dice`, ordered by
+    /// player and piece.
+    pub fn get_actions(&self, dice: u8) -> Actions {
+        if dice == 6 && self.rules.sixes_exhausted(self.six_rolled) {
+            return Actions::new();
+        }
+        self.board
+            .actions_with(&self.occupancy(), dice, self.turn, &self.rules)
+    }
+
+    /// What's on each square of the board: the one kept up to date by the
+    /// moves played, or one worked out afresh if there's none.
+    pub fn occupancy(&self) -> Cow<'_, Occupancy> {
+        match &self.occupancy {
+            Some(occupancy) if occupancy.fits(&self.rules) => Cow::Borrowed(occupancy),
+            _ => Cow::Owned(Occupancy::new(&self.board, &self.rules)),
+        }
+    }
+
+    /// Players in the order they got all their pieces to the last place.
+    ///
+    /// Without teams, the last player left is appended once everyone else
+    /// has finished.
+    pub fn finishing_order(&self) -> &[Player] {
+        &self.finishing_order
+    }
+
+    pub fn winner(&self) -> Option<Side> {
+        if self.rules.team_mode {
+            self.board.who_won(&self.rules).map(Side::Team)
+        } else {
+            self.finishing_order.first().copied().map(Side::Player)
+        }
+    }
+
+    /// Whether there's nothing left to play: a team has won, or every
+    /// player has a place in the finishing order.
+    pub fn is_over(&self) -> bool {
+        if self.rules.team_mode {
+            self.winner().is_some()
+        } else {
+            self.finishing_order.len() == self.rules.seats.len()
+        }
+    }
+
+    fn record_finishers(&mut self) {
+        for &player in &self.rules.seats {
+            if self.board.has_finished(player, &self.rules)
+                && !self.finishing_order.contains(&player)
+            {
+                self.finishing_order.push(player);
+            }
+        }
+        if !self.rules.team_mode {
+            let remaining = self
+                .rules
+                .seats
+                .iter()
+                .filter(|player| !self.finishing_order.contains(player))
+                .collect_vec();
+            if let [&last] = remaining[..] {
+                self.finishing_order.push(last);
+            }
+        }
+    }
+
+    fn next_turn(&mut self) {
+        self.six_rolled = 0;
+        for _ in 0..self.rules.seats.len() {
+            self.turn = self.turn.next(&self.rules);
+            if self.rules.team_mode || !self.finishing_order.contains(&self.turn) {
+                break;
+            }
+        }
+    }
+
+    /// Checks `action` could be played by the player to move after
+    /// rolling `dice`, short of looking at the destination square.
+    fn check_action(&self, dice: u8, action: &Action) -> Result<(), IllegalMove> {
+        let rules = &*self.rules;
+        let last_place = rules.geometry.last_place();
+        if !(1..=6).contains(&dice) {
+            return Err(IllegalMove::BadDice(dice));
+        }
+        let wrong_player = IllegalMove::WrongPlayer {
+            player: action.player,
+            turn: self.turn,
+        };
+        if !rules.is_seated(action.player) {
+            return Err(wrong_player);
+        }
+        if action.player != self.turn {
+            // Teammates only help each other out of the yard and into the
+            // last place.
+            let helps = (action.from == 0 || action.to == last_place)
+                && self.turn.teammates(rules).contains(&action.player);
+            if !helps {
+                return Err(wrong_player);
+            }
+        }
+        if action.piece >= 4 {
+            return Err(IllegalMove::NoSuchPiece(action.piece));
+        }
+        let found = self.board.players[action.player][action.piece];
+        if found != action.from {
+            return Err(IllegalMove::StaleFrom {
+                expected: action.from,
+                found,
+            });
+        }
+        if dice == 6 && rules.sixes_exhausted(self.six_rolled) {
+            return Err(IllegalMove::SixesExhausted);
+        }
+        let expected = if action.from == 0 {
+            if !rules.can_enter(dice) {
+                return Err(IllegalMove::EntryNeeds(rules.entry_rolls.clone()));
+            }
+            1
+        } else {
+            action.from + dice
+        };
+        if expected > last_place {
+            return Err(IllegalMove::Overshoot {
+                to: expected,
+                last: last_place,
+            });
+        }
+        if action.to != expected {
+            return Err(IllegalMove::WrongDestination {
+                expected,
+                found: action.to,
+            });
+        }
+        Ok(())
+    }
+
+    /// Plays `action` after rolling `dice`, or passes if it's `None`.
+    ///
+    /// The action has to be legal already.
+    fn play(&mut self, dice: u8, action: Option<Action>) -> Option<MoveOutcome> {
+        let player = self.turn;
         if dice == 6 {
-            self.six_rolled += 1;
+            self.six_rolled = self.six_rolled.saturating_add(1);
         } else {
             self.six_rolled = 0;
         }
-        let mut hold_turn = self.six_rolled > 0 && self.six_rolled < 3;
-        if !actions.is_empty() {
-            let i = action_chooser(self, &actions);
-            let action = actions[i];
-            hold_turn = self.board.apply_action(action) || hold_turn;
+        let max_sixes = self.rules.max_consecutive_sixes;
+        let mut hold_turn = self.six_rolled > 0 && (max_sixes == 0 || self.six_rolled < max_sixes);
+        let outcome = action.map(|action| {
+            let mut occupancy = self.occupancy().into_owned();
+            let before = self.board;
+            let outcome = self
+                .board
+                .apply_action_with(&occupancy, action, &self.rules)
+                .expect("legal actions can be applied");
+            occupancy.apply(&before, action, outcome);
+            self.occupancy = Some(occupancy);
+            hold_turn |= outcome.another_turn;
+            if outcome.finished {
+                self.record_finishers();
+            }
+            outcome
+        });
+        if !hold_turn || (!self.rules.team_mode && self.finishing_order.contains(&self.turn)) {
+            self.next_turn();
         }
-        if !hold_turn {
-            self.turn = NEXT_PLAYER[self.turn];
-            self.six_rolled = 0;
+        outcome.map(|outcome| MoveOutcome {
+            another_turn: self.turn == player,
+            ..outcome
+        })
+    }
+
+    /// Plays `action` after rolling `dice`, leaving the state untouched if
+    /// it isn't legal.
+    pub fn apply_action(&mut self, dice: u8, action: Action) -> Result<MoveOutcome, IllegalMove> {
+        self.check_action(dice, &action)?;
+        let mut board = self.board;
+        board.apply_action_with(&self.occupancy(), action, &self.rules)?;
+        Ok(self.play(dice, Some(action)).unwrap())
+    }
+
+    /// Rolls `dice` and lets `action_chooser` pick one of the legal actions,
+    /// if there are any. Returns what the chosen action did.
+    ///
+    /// The state is left untouched if the chooser picks an index outside
+    /// the actions it was given.
+    pub fn roll(
+        &mut self,
+        dice: u8,
+        action_chooser: impl FnOnce(&GameState, &[Action]) -> usize,
+    ) -> Result<Option<MoveOutcome>, IllegalMove> {
+        if !(1..=6).contains(&dice) {
+            return Err(IllegalMove::BadDice(dice));
         }
+        // In a fixed order, so that seeded bots make the same choices.
+        let actions = self.get_actions(dice);
+        let action = if actions.is_empty() {
+            None
+        } else {
+            let index = action_chooser(self, &actions);
+            Some(*actions.get(index).ok_or(IllegalMove::NoSuchChoice {
+                index,
+                choices: actions.len(),
+            })?)
+        };
+        Ok(self.play(dice, action))
     }
 }
 
-impl Display for GameState {
+/// Why a `GameState` can't come up in a game played under its rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidState {
+    /// A seat the board has no room for.
+    UnknownSeat(Player),
+    /// A seated player whose pieces weren't saved.
+    MissingSeat(Player),
+    /// The player to move doesn't have a seat.
+    EmptySeatToMove(Player),
+    /// A piece beyond the last place.
+    PositionOutOfRange {
+        player: Player,
+        piece: u8,
+        position: PiecePosition,
+    },
+    /// Two pieces on the same square outside the yard and the last place.
+    SharedSquare {
+        location: PieceLocation,
+        players: (Player, Player),
+    },
+    /// More sixes in a row than the rules let a turn go on for.
+    SixStreak(u8),
+    /// A player missing from, repeated in, or wrongly placed in the
+    /// finishing order.
+    FinishingOrder(Player),
+}
+
+impl Display for InvalidState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidState::UnknownSeat(player) => {
+                write!(f, "{:?} has no seat on this board", player)
+            }
+            InvalidState::MissingSeat(player) => {
+                write!(f, "{:?} is seated but has no pieces on the board", player)
+            }
+            InvalidState::EmptySeatToMove(player) => {
+                write!(f, "{:?} is to move but isn't seated", player)
+            }
+            InvalidState::PositionOutOfRange {
+                player,
+                piece,
+                position,
+            } => write!(
+                f,
+                "{:?}'s piece {} is at {}, past the last place",
+                player, piece, position
+            ),
+            InvalidState::SharedSquare {
+                location,
+                players: (first, second),
+            } => write!(
+                f,
+                "{:?} and {:?} both have a piece on {:?}",
+                first, second, location
+            ),
+            InvalidState::SixStreak(six_rolled) => {
+                write!(
+                    f,
+                    "{} sixes in a row should have ended the turn",
+                    six_rolled
+                )
+            }
+            InvalidState::FinishingOrder(player) => {
+                write!(
+                    f,
+                    "finishing order doesn't match the board for {:?}",
+                    player
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidState {}
+
+/// The pieces of the players on a saved board, written in seat order.
+#[derive(Debug)]
+struct SavedBoard(Vec<(Player, PlayerData)>);
+
+impl Serialize for SavedBoard {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_map(self.0.iter().map(|(player, data)| (player, data)))
+    }
+}
+
+impl<'de> Deserialize<'de> for SavedBoard {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let players = HashMap::<Player, PlayerData>::deserialize(deserializer)?;
+        Ok(SavedBoard(players.into_iter().collect()))
+    }
+}
+
+/// A `GameState` as it's saved, before it's checked.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SavedState {
+    board: SavedBoard,
+    turn: Player,
+    rules: RuleSet,
+    six_rolled: u8,
+    finishing_order: Vec<Player>,
+}
+
+impl From<GameState> for SavedState {
+    fn from(state: GameState) -> Self {
+        let seated = |&player: &Player| (player, state.board.players[player]);
+        SavedState {
+            board: SavedBoard(state.rules.seats.iter().map(seated).collect()),
+            turn: state.turn,
+            rules: (*state.rules).clone(),
+            six_rolled: state.six_rolled,
+            finishing_order: state.finishing_order,
+        }
+    }
+}
+
+impl TryFrom<SavedState> for GameState {
+    type Error = InvalidState;
+
+    fn try_from(saved: SavedState) -> Result<Self, Self::Error> {
+        if let Some(player) = saved.rules.unknown_seat() {
+            return Err(InvalidState::UnknownSeat(player));
+        }
+        let mut board = Board::default();
+        for &(player, data) in &saved.board.0 {
+            board.players[player] = data;
+        }
+        if let Some(&player) = saved
+            .rules
+            .seats
+            .iter()
+            .find(|&&player| saved.board.0.iter().all(|&(other, _)| other != player))
+        {
+            return Err(InvalidState::MissingSeat(player));
+        }
+        let state = GameState::from_parts(
+            board,
+            saved.turn,
+            saved.rules,
+            saved.six_rolled,
+            Some(saved.finishing_order),
+        );
+        state.validate()?;
+        Ok(state)
+    }
+}
+
+type Cell = (char, Color, Color);
+
+const PIECE: char = '◉';
+
+fn player_colors() -> EnumMap<Player, (Color, Color)> {
+    enum_map! {
+        Player::First => (Color::TrueColor { r: 0, g: 100, b: 255 }, Color::Blue),
+        Player::Second => (Color::TrueColor { r: 200, g: 0, b: 0 }, Color::Red),
+        Player::Third => (Color::TrueColor { r: 50, g: 150, b: 0 }, Color::Green),
+        Player::Fourth => (Color::TrueColor { r: 200, g: 120, b: 0 }, Color::Yellow),
+        Player::Fifth => (Color::TrueColor { r: 150, g: 50, b: 200 }, Color::Magenta),
+        Player::Sixth => (Color::TrueColor { r: 0, g: 180, b: 180 }, Color::Cyan),
+    }
+}
+
+fn write_grid<'a>(
+    f: &mut std::fmt::Formatter<'_>,
+    grid: impl IntoIterator<Item = &'a [Cell]>,
+) -> std::fmt::Result {
+    let mut s = String::new();
+    for row in grid {
+        for &(c, fg, bg) in row {
+            s.clear();
+            s.push(c);
+            write!(f, "{}", s.color(fg).on_color(bg))?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+impl GameState {
+    fn count_at(&self, player: Player, position: PiecePosition) -> usize {
+        self.board.players[player]
+            .pieces_positions
+            .iter()
+            .filter(|&&pos| pos == position)
+            .count()
+    }
+
+    fn fmt_standard(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let geometry = Geometry::Standard;
         let mut grid = [[(' ', Color::White, Color::Black); 13]; 13];
-        for i in 0..13 {
-            let c = Color::White;
-            grid[i][0].2 = c;
-            grid[i][12].2 = c;
-            grid[0][i].2 = c;
-            grid[12][i].2 = c;
-        }
-
-        let player_colors = enum_map! {
-            Player::First => (Color::TrueColor { r: 0, g: 100, b: 255 }, Color::Blue),
-            Player::Second => (Color::TrueColor { r: 200, g: 0, b: 0 }, Color::Red),
-            Player::Third => (Color::TrueColor { r: 50, g: 150, b: 0 }, Color::Green),
-            Player::Fourth => (Color::TrueColor { r: 200, g: 120, b: 0 }, Color::Yellow),
-        };
+        let c = Color::White;
+        for row in grid.iter_mut() {
+            row[0].2 = c;
+            row[12].2 = c;
+        }
+        for row in [0, 12] {
+            for cell in grid[row].iter_mut() {
+                cell.2 = c;
+            }
+        }
 
-        let home_location = enum_map! {
+        let player_colors = player_colors();
+
+        let home_location = |player| match player {
             Player::First => (8, 8),
             Player::Second => (1, 8),
             Player::Third => (1, 1),
-            Player::Fourth => (8, 1),
+            _ => (8, 1),
         };
 
-        for (p, (x, y)) in home_location {
+        for &p in geometry.seats() {
+            let (x, y) = home_location(p);
             let c = player_colors[p].1;
             for i in 0..4 {
                 grid[i + y][x].2 = c;
@@ -91,17 +624,17 @@ impl Display for GameState {
             }
         }
 
-        let piece = '◉';
-
-        let _board = self.board.render_board();
-
-        for (x, y) in SAFE_SPOTS {
+        for &(x, y) in &self.rules.safe_spots {
             grid[(y + 6) as usize][(x + 6) as usize].0 = '▵';
         }
 
-        for (player, positions) in POSITIONS.iter() {
+        for &player in geometry.seats() {
+            if !self.rules.is_seated(player) {
+                continue;
+            }
+            let positions = geometry.positions(player);
             let c = player_colors[player];
-            for i in [1, LAST_PLACE - 3, LAST_PLACE - 2, LAST_PLACE - 1] {
+            for i in iter::once(1).chain(geometry.home_column()) {
                 let p = positions[i as usize];
                 grid[(p.1 + 6) as usize][(p.0 + 6) as usize].2 = c.1;
             }
@@ -111,31 +644,23 @@ impl Display for GameState {
                     let (x, y) = positions[piece_index as usize];
                     let x = (x + 6) as usize;
                     let y = (y + 6) as usize;
-                    grid[y][x].0 = piece;
+                    grid[y][x].0 = PIECE;
                     grid[y][x].1 = c.0;
                 }
             }
-            let end_count = self.board.players[player]
-                .pieces_positions
-                .iter()
-                .filter(|&&pos| pos == LAST_PLACE)
-                .count();
+            let end_count = self.count_at(player, LAST_PLACE);
             if end_count > 1 {
                 let (x, y) = positions[LAST_PLACE as usize];
                 let x = (x + 6) as usize;
                 let y = (y + 6) as usize;
                 grid[y][x].0 = "01234".chars().nth(end_count).unwrap();
             }
-            let home_count = self.board.players[player]
-                .pieces_positions
-                .iter()
-                .filter(|&&pos| pos == 0)
-                .count();
-            let home = home_location[player];
+            let home_count = self.count_at(player, 0);
+            let home = home_location(player);
             for (x, y) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter().take(home_count) {
                 let x = home.0 + x + 1;
                 let y = home.1 + y + 1;
-                grid[y][x].0 = piece;
+                grid[y][x].0 = PIECE;
                 grid[y][x].1 = c.0;
             }
         }
@@ -148,17 +673,97 @@ impl Display for GameState {
             }
         }
 
-        let mut s = String::new();
-        for y in 0usize..13 {
-            for x in 0usize..13 {
-                s.clear();
-                s.push(grid[y][x].0);
-                write!(f, "{}", s.color(grid[y][x].1).on_color(grid[y][x].2))?;
+        write_grid(f, grid.iter().map(|row| &row[..]))
+    }
+
+    fn fmt_hexagonal(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let geometry = Geometry::Hexagonal;
+        let last_place = geometry.last_place();
+        // Every other column is left blank so neighbouring hexes line up.
+        let screen = |(q, r): PieceLocation| (2 * q + r, r);
+        let cells = geometry
+            .seats()
+            .iter()
+            .flat_map(|&player| geometry.positions(player).iter().copied().map(screen))
+            .chain([screen((0, 0))])
+            .collect_vec();
+        let (min_x, max_x) = cells
+            .iter()
+            .map(|&(x, _)| x)
+            .minmax()
+            .into_option()
+            .unwrap();
+        let (min_y, max_y) = cells
+            .iter()
+            .map(|&(_, y)| y)
+            .minmax()
+            .into_option()
+            .unwrap();
+        let mut grid = vec![
+            vec![(' ', Color::White, Color::Black); (max_x - min_x + 1) as usize];
+            (max_y - min_y + 1) as usize
+        ];
+        let at = |location: PieceLocation| {
+            let (x, y) = screen(location);
+            ((y - min_y) as usize, (x - min_x) as usize)
+        };
+
+        for &player in geometry.seats() {
+            for &location in &geometry.positions(player)[1..] {
+                let (y, x) = at(location);
+                grid[y][x] = ('·', Color::White, Color::BrightBlack);
             }
-            writeln!(f)?;
         }
+        for &location in &self.rules.safe_spots {
+            let (y, x) = at(location);
+            grid[y][x].0 = '▵';
+        }
+        let (y, x) = at((0, 0));
+        grid[y][x].2 = Color::BrightBlack;
 
-        Ok(())
+        let player_colors = player_colors();
+        for &player in geometry.seats() {
+            if !self.rules.is_seated(player) {
+                continue;
+            }
+            let positions = geometry.positions(player);
+            let c = player_colors[player];
+            for i in iter::once(1).chain(geometry.home_column()) {
+                let (y, x) = at(positions[i as usize]);
+                grid[y][x].2 = c.1;
+            }
+
+            for &piece_index in self.board.players[player].pieces_positions.iter() {
+                if piece_index != 0 {
+                    let (y, x) = at(positions[piece_index as usize]);
+                    grid[y][x].0 = PIECE;
+                    grid[y][x].1 = c.0;
+                }
+            }
+            let end_count = self.count_at(player, last_place);
+            if end_count > 1 {
+                let (y, x) = at(positions[last_place as usize]);
+                grid[y][x].0 = "01234".chars().nth(end_count).unwrap();
+            }
+            // The yard is a single cell, so it shows how many pieces are in it.
+            let home_count = self.count_at(player, 0);
+            if home_count > 0 {
+                let (y, x) = at(positions[0]);
+                grid[y][x].0 = "01234".chars().nth(home_count).unwrap();
+                grid[y][x].1 = c.0;
+            }
+        }
+
+        write_grid(f, grid.iter().map(|row| &row[..]))
+    }
+}
+
+impl Display for GameState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.rules.geometry {
+            Geometry::Standard => self.fmt_standard(f),
+            Geometry::Hexagonal => self.fmt_hexagonal(f),
+        }
     }
 }
 
@@ -173,13 +778,21 @@ mod tests {
         let mut state = GameState::default();
 
         fn roll_6(state: &mut GameState) {
-            state.roll(6, |_, actions| {
-                actions
-                    .iter()
-                    .map(|action| if action.player == Player::First {action.to} else {0})
-                    .position_max()
-                    .unwrap()
-            })
+            state
+                .roll(6, |_, actions| {
+                    actions
+                        .iter()
+                        .map(|action| {
+                            if action.player == Player::First {
+                                action.to
+                            } else {
+                                0
+                            }
+                        })
+                        .position_max()
+                        .unwrap()
+                })
+                .unwrap();
         }
 
         let get_moved_piece = |state: &GameState| {
@@ -209,5 +822,155 @@ mod tests {
         assert_eq!(state.turn, Player::Second);
         let board_after = state.board;
         assert_eq!(board_before, board_after);
+
+        // However many sixes a loaded state claims.
+        let state = GameState::from_parts(
+            Board::default(),
+            Player::First,
+            RuleSet::classic(),
+            255,
+            None,
+        );
+        assert!(state.get_actions(6).is_empty());
+    }
+
+    #[test]
+    fn test_free_for_all_finishing_order() {
+        let rules = RuleSet {
+            team_mode: false,
+            ..RuleSet::classic()
+        };
+        let mut board = Board::default();
+        board.players[Player::Second].pieces_positions = [LAST_PLACE; 4];
+        board.players[Player::First].pieces_positions = [LAST_PLACE, LAST_PLACE, LAST_PLACE, 38];
+        board.players[Player::Third].pieces_positions = [LAST_PLACE, LAST_PLACE, LAST_PLACE, 38];
+        let mut state = GameState::with_rules(board, Player::First, rules);
+        assert_eq!(state.winner(), Some(Side::Player(Player::Second)));
+
+        state.roll(1, |_, _| 0).unwrap();
+        assert_eq!(state.finishing_order(), [Player::Second, Player::First]);
+        // The finish bonus is lost on finishing, and finished players are skipped.
+        assert_eq!(state.turn, Player::Third);
+        assert!(!state.is_over());
+
+        state.roll(1, |_, _| 0).unwrap();
+        assert_eq!(
+            state.finishing_order(),
+            [Player::Second, Player::First, Player::Third, Player::Fourth]
+        );
+        assert!(state.is_over());
+    }
+
+    #[test]
+    fn test_three_players() {
+        let mut state =
+            GameState::with_rules(Board::default(), Player::Third, RuleSet::three_player());
+        state.roll(2, |_, _| 0).unwrap();
+        assert_eq!(state.turn, Player::First);
+        state.roll(6, |_, _| 0).unwrap();
+        state.roll(2, |_, _| 0).unwrap();
+        assert_eq!(state.turn, Player::Second);
+
+        // The empty seat's pieces neither block nor get captured.
+        state.board_mut().players[Player::Second].pieces_positions[0] = 3;
+        state.board_mut().players[Player::Fourth].pieces_positions[0] = 22;
+        // Changing the board by hand drops what was worked out from it.
+        let rebuilt = |state: &GameState| {
+            GameState::from_parts(
+                state.board,
+                state.turn,
+                (*state.rules).clone(),
+                state.six_rolled,
+                Some(state.finishing_order.clone()),
+            )
+            .key()
+        };
+        assert_eq!(state.key(), rebuilt(&state));
+        let rendered = state.board.render_board(&state.rules);
+        assert!(rendered.get_position((-2, 1)).is_none());
+        state
+            .roll(1, |_, actions| {
+                actions.iter().position(|a| a.from == 3).unwrap()
+            })
+            .unwrap();
+        assert_eq!(state.board.players[Player::Second].pieces_positions[0], 4);
+        assert_eq!(state.turn, Player::Third);
+        assert_eq!(state.board.players[Player::Fourth].pieces_positions[0], 22);
+        assert_eq!(state.key(), rebuilt(&state));
+    }
+
+    #[test]
+    #[should_panic(expected = "Fifth has no seat on the Standard board")]
+    fn test_unknown_seat() {
+        let rules = RuleSet {
+            seats: vec![Player::First, Player::Fifth],
+            ..RuleSet::classic()
+        };
+        GameState::with_rules(Board::default(), Player::First, rules);
+    }
+
+    #[test]
+    fn test_illegal_moves() {
+        let mut board = Board::default();
+        board.players[Player::First].pieces_positions = [6, 36, 37, 0];
+        // Sits on First's 7th square.
+        board.players[Player::Second].pieces_positions[0] = 34;
+        let mut state = GameState::new(board, Player::First);
+        let action = |player, piece, from, to| Action {
+            player,
+            piece,
+            from,
+            to,
+        };
+
+        assert_eq!(
+            state.apply_action(3, action(Player::First, 3, 0, 1)),
+            Err(IllegalMove::EntryNeeds(vec![1, 6]))
+        );
+        assert!(matches!(
+            state.apply_action(1, action(Player::Second, 1, 0, 1)),
+            Err(IllegalMove::WrongPlayer { .. })
+        ));
+        // Teammates can be brought out of the yard.
+        assert!(state
+            .clone()
+            .apply_action(1, action(Player::Third, 0, 0, 1))
+            .is_ok());
+        assert_eq!(
+            state.apply_action(1, action(Player::First, 0, 5, 6)),
+            Err(IllegalMove::StaleFrom {
+                expected: 5,
+                found: 6
+            })
+        );
+        assert_eq!(
+            state.apply_action(4, action(Player::First, 1, 36, 40)),
+            Err(IllegalMove::Overshoot { to: 40, last: 39 })
+        );
+        assert!(matches!(
+            state.apply_action(1, action(Player::First, 1, 36, 37)),
+            Err(IllegalMove::Blocked { .. })
+        ));
+        assert_eq!(
+            state.roll(1, |_, _| 10),
+            Err(IllegalMove::NoSuchChoice {
+                index: 10,
+                choices: 4
+            })
+        );
+        assert_eq!(state.board, board);
+
+        let outcome = state
+            .apply_action(1, action(Player::First, 0, 6, 7))
+            .unwrap();
+        assert_eq!(outcome.captured, Some((Player::Second, 0)));
+        assert!(outcome.another_turn);
+        assert!(!outcome.finished);
+
+        state = GameState::from_parts(board, Player::First, RuleSet::classic(), 2, None);
+        assert_eq!(
+            state.apply_action(6, action(Player::First, 3, 0, 1)),
+            Err(IllegalMove::SixesExhausted)
+        );
     }
 }
@@ -0,0 +1,341 @@
+//! Bot scoring each move with a weighted sum of features.
+//!
+//! Every feature measures how a move changes the position of the piece it
+//! moves, so that weights can be read as "how much is this worth compared to
+//! progress". The weights are loaded from a JSON file, with any left out
+//! taking their default values.
+
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Action, Board, PieceLocationExt, PiecePosition, PiecePositionExt};
+use crate::bots::{piece_risk, Bot};
+use crate::game_state::GameState;
+use crate::rules::RuleSet;
+use crate::Player;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Weights {
+    /// Squares advanced, as a fraction of the whole track.
+    pub progress: f64,
+    /// Change in the dice values that let an enemy land on the piece, as in
+    /// `bots::piece_risk`, over six.
+    pub risk: f64,
+    /// Capturing a piece, plus the fraction of its track it had covered.
+    pub capture: f64,
+    /// Bringing a piece out, leaving one fewer at home.
+    pub home: f64,
+    /// Moving onto a safe spot, or off one.
+    pub safe_spot: f64,
+    /// Change in the enemy moves, over six, that the piece stops by sitting
+    /// where it can't be captured.
+    pub blocking: f64,
+    /// Change in the teammate pieces up to six squares ahead, which the
+    /// piece could avenge if they're captured.
+    pub teammate_support: f64,
+    /// Getting the piece to the end of its track.
+    pub finish: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            progress: 10.0,
+            risk: -2.0,
+            capture: 3.0,
+            home: 0.5,
+            safe_spot: 0.3,
+            blocking: 0.2,
+            teammate_support: 0.2,
+            finish: 4.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WeightsError {
+    Io(io::Error),
+    /// The file isn't JSON, or has fields that aren't weights.
+    Json(serde_json::Error),
+}
+
+impl Display for WeightsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WeightsError::Io(err) => write!(f, "couldn't access weights file: {}", err),
+            WeightsError::Json(err) => write!(f, "malformed weights file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for WeightsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WeightsError::Io(err) => Some(err),
+            WeightsError::Json(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for WeightsError {
+    fn from(err: io::Error) -> Self {
+        WeightsError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for WeightsError {
+    fn from(err: serde_json::Error) -> Self {
+        WeightsError::Json(err)
+    }
+}
+
+impl Weights {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, WeightsError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), WeightsError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The weights in the order they're declared in, for optimisers that
+    /// work on plain vectors.
+    pub fn to_array(&self) -> [f64; 8] {
+        [
+            self.progress,
+            self.risk,
+            self.capture,
+            self.home,
+            self.safe_spot,
+            self.blocking,
+            self.teammate_support,
+            self.finish,
+        ]
+    }
+
+    pub fn from_array(weights: [f64; 8]) -> Self {
+        let [progress, risk, capture, home, safe_spot, blocking, teammate_support, finish] =
+            weights;
+        Self {
+            progress,
+            risk,
+            capture,
+            home,
+            safe_spot,
+            blocking,
+            teammate_support,
+            finish,
+        }
+    }
+
+    /// How much `action` is worth to the player moving in `state`.
+    pub fn score(&self, state: &GameState, action: &Action) -> f64 {
+        let features = Features::of(state, action);
+        self.progress * features.progress
+            + self.risk * features.risk
+            + self.capture * features.capture
+            + self.home * features.home
+            + self.safe_spot * features.safe_spot
+            + self.blocking * features.blocking
+            + self.teammate_support * features.teammate_support
+            + self.finish * features.finish
+    }
+}
+
+/// What a move changes, in the same units as the weights.
+#[derive(Debug, Default)]
+struct Features {
+    progress: f64,
+    risk: f64,
+    capture: f64,
+    home: f64,
+    safe_spot: f64,
+    blocking: f64,
+    teammate_support: f64,
+    finish: f64,
+}
+
+/// Enemy moves that would land on the piece `player` has at `position`,
+/// and can't because it's safe there.
+fn blocked_moves(board: &Board, rules: &RuleSet, player: Player, position: PiecePosition) -> usize {
+    let geometry = rules.geometry;
+    if position == 0 || position.is_last(geometry) {
+        return 0;
+    }
+    let pos = position.get_coords(player, geometry);
+    if !pos.is_safe(rules) && !position.is_home() {
+        return 0;
+    }
+    player
+        .enemies(rules)
+        .into_iter()
+        .flat_map(|enemy| {
+            board.players[enemy]
+                .pieces_positions
+                .into_iter()
+                .filter(|&from| from != 0)
+                .flat_map(move |from| (1..=6).map(move |dice| (enemy, from + dice)))
+        })
+        .filter(|&(enemy, to)| to < geometry.last_place() && to.get_coords(enemy, geometry) == pos)
+        .count()
+}
+
+/// Teammate pieces up to six squares ahead of the piece `player` has at
+/// `position`.
+fn supported_pieces(
+    board: &Board,
+    rules: &RuleSet,
+    player: Player,
+    position: PiecePosition,
+) -> usize {
+    let geometry = rules.geometry;
+    if position == 0 {
+        return 0;
+    }
+    let ahead = (1..=6)
+        .map(|squares| position + squares)
+        .filter(|&to| to < geometry.last_place())
+        .map(|to| to.get_coords(player, geometry))
+        .collect::<Vec<_>>();
+    player
+        .teammates(rules)
+        .into_iter()
+        .flat_map(|teammate| {
+            board.players[teammate]
+                .pieces_positions
+                .into_iter()
+                .filter(move |&pos| pos != 0 && !pos.is_last(geometry))
+                .map(move |pos| pos.get_coords(teammate, geometry))
+        })
+        .filter(|pos| ahead.contains(pos))
+        .count()
+}
+
+impl Features {
+    fn of(state: &GameState, action: &Action) -> Self {
+        let rules = &state.rules;
+        let last_place = rules.geometry.last_place() as f64;
+        let before = state.board();
+        let occupancy = state.occupancy();
+        let mut after = *before;
+        let Ok(outcome) = after.apply_action_with(&occupancy, *action, rules) else {
+            return Self::default();
+        };
+        let mut after_occupancy = *occupancy;
+        after_occupancy.apply(before, *action, outcome);
+        let (player, from, to) = (action.player, action.from, action.to);
+        let safe = |position: PiecePosition| {
+            position != 0
+                && !position.is_last(rules.geometry)
+                && position.get_coords(player, rules.geometry).is_safe(rules)
+        };
+        Self {
+            progress: (to - from) as f64 / last_place,
+            risk: (piece_risk(&after, &after_occupancy, rules, player, to) as f64
+                - piece_risk(before, &occupancy, rules, player, from) as f64)
+                / 6.0,
+            capture: outcome.captured.map_or(0.0, |(enemy, piece)| {
+                1.0 + before.players[enemy][piece] as f64 / last_place
+            }),
+            home: if from == 0 { 1.0 } else { 0.0 },
+            safe_spot: safe(to) as u8 as f64 - safe(from) as u8 as f64,
+            blocking: (blocked_moves(&after, rules, player, to) as f64
+                - blocked_moves(before, rules, player, from) as f64)
+                / 6.0,
+            teammate_support: supported_pieces(&after, rules, player, to) as f64
+                - supported_pieces(before, rules, player, from) as f64,
+            finish: if outcome.finished { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+/// Plays the move with the highest score, the first one listed on ties.
+#[derive(Clone, Debug, Default)]
+pub struct HeuristicBot {
+    pub weights: Weights,
+}
+
+impl HeuristicBot {
+    pub fn new(weights: Weights) -> Self {
+        Self { weights }
+    }
+}
+
+impl Bot for HeuristicBot {
+    fn choose(
+        &mut self,
+        state: &GameState,
+        _dice: u8,
+        actions: &[Action],
+        _rng: &mut dyn RngCore,
+    ) -> usize {
+        if actions.len() == 1 {
+            return 0;
+        }
+        let mut best = (0, f64::NEG_INFINITY);
+        for (i, action) in actions.iter().enumerate() {
+            let score = self.weights.score(state, action);
+            if score > best.1 {
+                best = (i, score);
+            }
+        }
+        best.0
+    }
+
+    fn box_clone(&self) -> Box<dyn Bot> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weights() {
+        let mut state = GameState::default();
+        state.board_mut().players[Player::First].pieces_positions = [5, 20, 0, 0];
+        state.board_mut().players[Player::Second].pieces_positions = [2, 0, 0, 0];
+        let mut actions = state.get_actions(6).into_iter().collect::<Vec<_>>();
+        actions.sort_by_key(|action| (action.player as usize, action.piece));
+        let choose = |weights: Weights| {
+            let mut bot = HeuristicBot::new(weights);
+            let action = actions[bot.choose(&state, 6, &actions, &mut rand::thread_rng())];
+            (action.player, action.piece)
+        };
+
+        // Our piece 0 takes the enemy on square 11 of our track, and either
+        // team can bring a piece out, our teammate's next to one of ours.
+        assert_eq!(choose(Weights::default()), (Player::First, 0));
+        let home = Weights {
+            capture: 0.0,
+            home: 5.0,
+            ..Default::default()
+        };
+        assert_eq!(choose(home), (Player::Third, 0));
+        let alone = Weights {
+            teammate_support: 0.0,
+            ..home
+        };
+        assert_eq!(choose(alone), (Player::First, 2));
+
+        // Named after the test and the process, so that concurrent runs
+        // don't share the file.
+        let path = std::env::temp_dir().join(format!(
+            "ludo_engine_test_weights_{}.json",
+            std::process::id()
+        ));
+        fs::write(&path, r#"{"home": 5.0, "capture": 0.0}"#).unwrap();
+        assert_eq!(Weights::load(&path).unwrap(), home);
+        fs::write(&path, r#"{"homes": 5.0}"#).unwrap();
+        assert!(matches!(Weights::load(&path), Err(WeightsError::Json(_))));
+        fs::remove_file(&path).unwrap();
+    }
+}
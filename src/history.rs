@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Action, IllegalMove};
+use crate::game_state::GameState;
+use crate::Player;
+
+/// Everything that happened in one roll of the dice.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ply {
+    /// Player who rolled.
+    pub player: Player,
+    pub dice: u8,
+    /// Action played, or `None` when there was nothing to play.
+    pub action: Option<Action>,
+    /// Owner and index of the piece sent back to the yard.
+    pub captured: Option<(Player, u8)>,
+    /// Player to move after this ply.
+    pub next_turn: Player,
+}
+
+/// A game that remembers how it got to its current state, so it can be
+/// stepped back and forth.
+///
+/// Playing a roll after undoing discards the undone plies.
+#[derive(Clone)]
+pub struct GameHistory {
+    /// State before each ply, followed by the state after the last one.
+    states: Vec<GameState>,
+    plies: Vec<Ply>,
+    /// Plies currently applied.
+    position: usize,
+}
+
+impl GameHistory {
+    pub fn new(state: GameState) -> Self {
+        Self {
+            states: vec![state],
+            plies: vec![],
+            position: 0,
+        }
+    }
+
+    pub fn current(&self) -> &GameState {
+        &self.states[self.position]
+    }
+
+    pub fn initial(&self) -> &GameState {
+        &self.states[0]
+    }
+
+    /// Every recorded ply, including undone ones that can still be redone.
+    pub fn plies(&self) -> &[Ply] {
+        &self.plies
+    }
+
+    /// Number of plies played to reach the current state.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn roll(
+        &mut self,
+        dice: u8,
+        action_chooser: impl FnOnce(&GameState, &[Action]) -> usize,
+    ) -> Result<&Ply, IllegalMove> {
+        let before = self.current();
+        let mut after = before.clone();
+        let mut action = None;
+        let outcome = after.roll(dice, |state, actions| {
+            let i = action_chooser(state, actions);
+            action = actions.get(i).copied();
+            i
+        })?;
+        let ply = Ply {
+            player: before.turn,
+            dice,
+            action,
+            captured: outcome.and_then(|outcome| outcome.captured),
+            next_turn: after.turn,
+        };
+        Ok(self.push(after, ply))
+    }
+
+    /// Plays `action` after rolling `dice`, as long as it's legal, whether
+    /// or not it's one of the actions `roll` would offer.
+    pub fn apply_action(&mut self, dice: u8, action: Action) -> Result<&Ply, IllegalMove> {
+        let before = self.current();
+        let mut after = before.clone();
+        let outcome = after.apply_action(dice, action)?;
+        let ply = Ply {
+            player: before.turn,
+            dice,
+            action: Some(action),
+            captured: outcome.captured,
+            next_turn: after.turn,
+        };
+        Ok(self.push(after, ply))
+    }
+
+    /// Adds `ply`, which led to `after`, in place of any undone plies.
+    fn push(&mut self, after: GameState, ply: Ply) -> &Ply {
+        self.states.truncate(self.position + 1);
+        self.plies.truncate(self.position);
+        self.states.push(after);
+        self.plies.push(ply);
+        self.position += 1;
+        &self.plies[self.position - 1]
+    }
+
+    /// Takes back the last ply, returning it.
+    pub fn undo(&mut self) -> Option<&Ply> {
+        self.position = self.position.checked_sub(1)?;
+        Some(&self.plies[self.position])
+    }
+
+    /// Replays the last undone ply, returning it.
+    pub fn redo(&mut self) -> Option<&Ply> {
+        if self.position == self.plies.len() {
+            return None;
+        }
+        self.position += 1;
+        Some(&self.plies[self.position - 1])
+    }
+
+    /// Moves to the state after `position` plies, which may be before or
+    /// after the current one. Returns false if it was never reached.
+    pub fn go_to(&mut self, position: usize) -> bool {
+        if position > self.plies.len() {
+            return false;
+        }
+        self.position = position;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut board = Board::default();
+        board.players[Player::First].pieces_positions[0] = 6;
+        // Sits on First's 7th square.
+        board.players[Player::Second].pieces_positions[0] = 34;
+        let mut history = GameHistory::new(GameState::new(board, Player::First));
+
+        let capture = *history
+            .roll(1, |_, actions| {
+                actions.iter().position(|action| action.from == 6).unwrap()
+            })
+            .unwrap();
+        assert_eq!(capture.captured, Some((Player::Second, 0)));
+        assert_eq!(capture.next_turn, Player::First);
+        history.roll(3, |_, _| 0).unwrap();
+        assert_eq!(history.current().turn, Player::Second);
+
+        let last = history.plies()[1];
+        assert_eq!(history.undo().copied(), Some(last));
+        assert_eq!(history.undo().copied(), Some(capture));
+        assert!(history.undo().is_none());
+        assert_eq!(*history.current().board(), board);
+
+        assert_eq!(history.redo().copied(), Some(capture));
+        assert_eq!(
+            history.current().board().players[Player::Second].pieces_positions[0],
+            0
+        );
+
+        // A different move replaces the undone one.
+        history.roll(2, |_, _| 0).unwrap();
+        assert_eq!(history.plies().len(), 2);
+        assert!(history.roll(7, |_, _| 0).is_err());
+        assert_eq!(history.plies().len(), 2);
+        assert!(history.redo().is_none());
+        assert!(history.go_to(0));
+        assert_eq!(*history.current().board(), board);
+    }
+}
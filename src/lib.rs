@@ -1,28 +1,47 @@
-use board::Team;
+use board::{Side, Team};
 use enum_iterator::Sequence;
 use enum_map::{enum_map, Enum, EnumMap};
 use lazy_static::lazy_static;
-use serde::{Serialize, Deserialize};
+use rules::RuleSet;
+use serde::{Deserialize, Serialize};
 
 pub mod board;
 pub mod bots;
+pub mod dice;
+pub mod expectiminimax;
 pub mod game_state;
+pub mod heuristic;
+pub mod history;
+pub mod mcts;
+pub mod notation;
 pub mod positions;
+pub mod record;
+pub mod rollout;
+pub mod rules;
+pub mod save;
 pub mod simulator;
+pub mod solver;
+pub mod symmetry;
+pub mod tablebase;
+pub mod tournament;
+pub mod transposition;
+pub mod tuning;
 pub mod utils;
+pub mod zobrist;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Sequence, Hash, PartialEq, Eq, Enum)]
+/// A seat at the table. The last two are only on the hexagonal board, but
+/// maps keyed by `Player` have room for all six whatever the rules.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Sequence, Hash, PartialEq, Eq, Enum, Default,
+)]
 pub enum Player {
+    #[default]
     First = 0,
     Second,
     Third,
     Fourth,
-}
-
-impl Default for Player {
-     fn default() -> Self {
-         Self::First
-     }
+    Fifth,
+    Sixth,
 }
 
 lazy_static! {
@@ -30,34 +49,58 @@ lazy_static! {
         Player::First => Player::Second,
         Player::Second => Player::Third,
         Player::Third => Player::Fourth,
-        Player::Fourth => Player::First,
+        Player::Fourth => Player::Fifth,
+        Player::Fifth => Player::Sixth,
+        Player::Sixth => Player::First,
     };
 }
 
 impl Player {
-    fn is_enemy_of(&self, other: Player) -> bool {
-        *self != other && other != self.teammate()
+    fn is_enemy_of(&self, other: Player, rules: &RuleSet) -> bool {
+        *self != other && !(rules.team_mode && self.team() == other.team())
     }
 
-    fn is_friendly_to(&self, other: Player) -> bool {
-        !self.is_enemy_of(other)
+    fn is_friendly_to(&self, other: Player, rules: &RuleSet) -> bool {
+        !self.is_enemy_of(other, rules)
     }
 
-    fn teammate(&self) -> Player {
-        NEXT_PLAYER[NEXT_PLAYER[*self]]
+    fn teammates(&self, rules: &RuleSet) -> Vec<Player> {
+        rules
+            .seats
+            .iter()
+            .copied()
+            .filter(|&other| other != *self && self.is_friendly_to(other, rules))
+            .collect()
     }
 
-    pub fn enemies(&self) -> [Player; 2] {
-        let first = NEXT_PLAYER[*self];
-        let second = first.teammate();
+    pub fn enemies(&self, rules: &RuleSet) -> Vec<Player> {
+        enum_iterator::all::<Player>()
+            .filter(|&other| rules.is_seated(other) && self.is_enemy_of(other, rules))
+            .collect()
+    }
 
-        [first, second]
+    /// The next seated player in turn order.
+    pub fn next(&self, rules: &RuleSet) -> Player {
+        let mut player = NEXT_PLAYER[*self];
+        while !rules.is_seated(player) && player != *self {
+            player = NEXT_PLAYER[player];
+        }
+        player
     }
 
     pub fn team(&self) -> Team {
         match self {
-            Player::First | Player::Third => Team::FirstThird,
-            Player::Second | Player::Fourth => Team::SecondFourth,
+            Player::First | Player::Third | Player::Fifth => Team::Odd,
+            Player::Second | Player::Fourth | Player::Sixth => Team::Even,
+        }
+    }
+
+    /// The side this player wins or loses with under `rules`.
+    pub fn side(&self, rules: &RuleSet) -> Side {
+        if rules.team_mode {
+            Side::Team(self.team())
+        } else {
+            Side::Player(*self)
         }
     }
 
@@ -67,6 +110,8 @@ impl Player {
             Player::Second => "Yellow",
             Player::Third => "Green",
             Player::Fourth => "Red",
+            Player::Fifth => "Purple",
+            Player::Sixth => "Cyan",
         }
     }
 }
@@ -1,10 +1,9 @@
-use enum_map::enum_map;
 use ludo_engine::{
     board::Board,
-    bots::{average_bot, choose_closest_to_target},
+    bots::{all_seats, average_bot, choose_closest_to_target},
+    dice::{DiceSource, SeededDice},
     game_state::GameState,
     simulator::get_best_action,
-    utils::roll_dice,
     *,
 };
 use rand::prelude::*;
@@ -19,41 +18,78 @@ fn main() {
             //_ => PlayerData::default(),
         //},
     //};
-    let mut state = GameState::new(board, Player::First);
+    // A position can be given in notation to start from, see `notation`.
+    let mut state = match std::env::args().nth(1) {
+        Some(notation) => GameState::from_notation(&notation).unwrap_or_else(|err| {
+            eprintln!("Invalid position: {}", err);
+            std::process::exit(1);
+        }),
+        None => GameState::new(board, Player::First),
+    };
     //println!("{}", &state);
     //let actions = state.get_actions(1);
 
     //dbg!(actions);
     //simulate(&mut state, Some(100));
-    let mut rng = thread_rng();
+    // Set LUDO_SEED to replay a game.
+    let seed = match std::env::var("LUDO_SEED") {
+        Ok(seed) => seed.parse().unwrap_or_else(|err| {
+            eprintln!("Invalid seed: {}", err);
+            std::process::exit(1);
+        }),
+        Err(_) => thread_rng().gen(),
+    };
+    println!("Seed: {}", seed);
+    // Set LUDO_TABLEBASE to a tablebase file to play the endgame from it.
+    let tablebase = std::env::var("LUDO_TABLEBASE").ok().map(|path| {
+        let tablebase = tablebase::Tablebase::load(&path).unwrap_or_else(|err| {
+            eprintln!("Invalid tablebase: {}", err);
+            std::process::exit(1);
+        });
+        tablebase
+    });
+    let mut rolls = SeededDice::new(seed);
 
-    while state.board.who_won().is_none() {
-        let dice = roll_dice(&mut rng);
+    while state.winner().is_none() {
+        let dice = rolls.roll().unwrap();
         println!("Player: {}", state.turn.name());
         println!("Dice: {}", dice);
         if [Player::First, Player::Second, Player::Third, Player::Fourth].contains(&state.turn) {
-            let team = state.turn.team();
-            state.roll(dice, |state, actions| {
-                let (best_action, win_rate) =
-                    get_best_action(state.clone(), dice, enum_map! {_ => average_bot}, team)
-                        .unwrap();
-                println!("{:5.03}%", win_rate * 100.0);
-                actions
-                    .iter()
-                    .position(|&action| action == best_action)
-                    .unwrap()
-            });
+            let side = state.turn.side(&state.rules);
+            let seed = rolls.rng().gen();
+            state
+                .roll(dice, |state, actions| {
+                    let (best_action, win_rate) = get_best_action(
+                        state.clone(),
+                        dice,
+                        all_seats(average_bot),
+                        side,
+                        seed,
+                        tablebase.as_ref(),
+                    )
+                    .unwrap();
+                    println!("{:5.03}%", win_rate * 100.0);
+                    actions
+                        .iter()
+                        .position(|&action| action == best_action)
+                        .unwrap()
+                })
+                .unwrap();
         } else {
-            state.roll(dice, choose_closest_to_target);
+            state
+                .roll(dice, |state, actions| {
+                    choose_closest_to_target(state, actions, rolls.rng())
+                })
+                .unwrap();
         }
         println!("{}", &state);
     }
 
-    println!("{:?} WON!", state.board.who_won().unwrap());
+    println!("{:?} WON!", state.winner().unwrap());
 
     //let mut stats = Stats::new();
     //loop {
-    //if simulate(state.clone()) == Team::FirstThird {
+    //if simulate(state.clone()) == Team::Odd {
     //stats.add_win();
     //} else {
     //stats.add_loss();
@@ -0,0 +1,408 @@
+//! Monte Carlo tree search bot.
+//!
+//! The tree alternates between states waiting for a roll and the choices
+//! each roll allows. Rolls are sampled as the search goes down, and the
+//! choices are picked with UCT, each player maximising its own chance of
+//! ending up on the winning side. Games are finished off by the rollout
+//! bots.
+
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use rand::RngCore;
+
+use crate::board::Action;
+use crate::bots::{all_seats, average_bot, Bot, Bots};
+use crate::dice::SeededDice;
+use crate::game_state::GameState;
+use crate::history::Ply;
+use crate::simulator::simulate_to_finish;
+use crate::utils::roll_dice;
+use crate::Player;
+
+/// How long to search for each move.
+#[derive(Clone, Copy, Debug)]
+pub enum Budget {
+    Iterations(u32),
+    Time(Duration),
+}
+
+#[derive(Clone)]
+pub struct MctsConfig {
+    pub budget: Budget,
+    /// UCT exploration constant.
+    pub exploration: f64,
+    /// Bots that play out games from the leaves of the tree.
+    pub rollout: Bots,
+    /// Bonus for the move the rollout bot would play, fading as the search
+    /// visits it, so that the search only strays from it on good evidence.
+    pub prior_bias: f64,
+    /// Whether to keep the part of the tree that's still reachable after
+    /// each move.
+    pub reuse_tree: bool,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            budget: Budget::Iterations(200),
+            exploration: 0.5,
+            rollout: all_seats(average_bot),
+            prior_bias: 5.0,
+            reuse_tree: true,
+        }
+    }
+}
+
+/// Chance of ending up on the winning side, for each seat.
+type Rewards = [f64; 6];
+
+/// One of the choices a roll allows, or passing when there are none.
+#[derive(Clone)]
+struct Edge {
+    action: Option<Action>,
+    visits: u32,
+    /// Sum of the rewards of the player who chooses.
+    reward: f64,
+    /// Whether the rollout bot would play it.
+    preferred: bool,
+    child: Option<usize>,
+}
+
+#[derive(Clone)]
+struct Node {
+    /// State waiting for a roll.
+    state: GameState,
+    /// Choices for each roll, once it has come up.
+    rolls: [Option<Vec<Edge>>; 6],
+}
+
+impl Node {
+    fn new(state: GameState) -> Self {
+        Self {
+            state,
+            rolls: Default::default(),
+        }
+    }
+
+    /// Choices for `dice`, listed the first time it comes up, with the one
+    /// `policy` would play marked as preferred.
+    fn expand(&mut self, dice: u8, policy: &mut dyn Bot, rng: &mut dyn RngCore) -> &mut [Edge] {
+        let state = &self.state;
+        self.rolls[dice as usize - 1].get_or_insert_with(|| {
+            let actions = state
+                .get_actions(dice)
+                .into_iter()
+                .sorted_by_key(|action| (action.player as usize, action.piece))
+                .collect_vec();
+            let preferred = match actions.len() {
+                0 | 1 => 0,
+                _ => policy.choose(state, dice, &actions, rng),
+            };
+            let edge = |(i, action)| Edge {
+                action,
+                visits: 0,
+                reward: 0.0,
+                preferred: i == preferred,
+                child: None,
+            };
+            if actions.is_empty() {
+                vec![edge((0, None))]
+            } else {
+                actions
+                    .into_iter()
+                    .map(Some)
+                    .enumerate()
+                    .map(edge)
+                    .collect()
+            }
+        })
+    }
+
+    fn edges(&mut self, dice: u8) -> &mut [Edge] {
+        self.rolls[dice as usize - 1]
+            .as_mut()
+            .expect("roll was expanded")
+    }
+}
+
+fn same_state(a: &GameState, b: &GameState) -> bool {
+    a.board() == b.board()
+        && a.turn == b.turn
+        && a.six_rolled() == b.six_rolled()
+        && a.finishing_order() == b.finishing_order()
+}
+
+fn rewards(state: &GameState) -> Rewards {
+    let mut rewards = [0.0; 6];
+    if let Some(winner) = state.winner() {
+        for player in enum_iterator::all::<Player>() {
+            if winner.contains(player) {
+                rewards[player as usize] = 1.0;
+            }
+        }
+    }
+    rewards
+}
+
+#[derive(Clone)]
+pub struct MctsBot {
+    pub config: MctsConfig,
+    /// Nodes of the search tree, with the root first.
+    nodes: Vec<Node>,
+}
+
+impl MctsBot {
+    pub fn new(config: MctsConfig) -> Self {
+        Self {
+            config,
+            nodes: vec![],
+        }
+    }
+
+    /// Nodes in the tree kept for the next move.
+    pub fn tree_size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn play(state: &GameState, dice: u8, action: Option<Action>) -> GameState {
+        let mut state = state.clone();
+        match action {
+            Some(action) => {
+                state
+                    .apply_action(dice, action)
+                    .expect("tree actions are legal");
+            }
+            None => {
+                state.roll(dice, |_, _| 0).expect("tree rolls are legal");
+            }
+        }
+        state
+    }
+
+    fn rollout(&mut self, state: &GameState, rng: &mut dyn RngCore) -> Rewards {
+        let mut state = state.clone();
+        if state.winner().is_none() {
+            let mut dice = SeededDice::new(rng.next_u64());
+            simulate_to_finish(&mut state, &mut self.config.rollout, &mut dice);
+        }
+        rewards(&state)
+    }
+
+    fn pick_edge(config: &MctsConfig, edges: &[Edge]) -> usize {
+        if let Some(untried) = edges.iter().position(|edge| edge.visits == 0) {
+            return untried;
+        }
+        let total = edges.iter().map(|edge| edge.visits).sum::<u32>() as f64;
+        let uct = |edge: &Edge| {
+            let visits = edge.visits as f64;
+            let bias = if edge.preferred {
+                config.prior_bias / (visits + 1.0)
+            } else {
+                0.0
+            };
+            edge.reward / visits + config.exploration * (total.ln() / visits).sqrt() + bias
+        };
+        edges
+            .iter()
+            .position_max_by(|a, b| uct(a).total_cmp(&uct(b)))
+            .unwrap()
+    }
+
+    /// Runs one iteration from the root, where `dice` has been rolled.
+    fn iterate(&mut self, dice: u8, rng: &mut dyn RngCore) {
+        let mut path = vec![];
+        let mut node = 0;
+        let mut roll = dice;
+        let rewards = loop {
+            if self.nodes[node].state.winner().is_some() {
+                break rewards(&self.nodes[node].state);
+            }
+            let mover = self.nodes[node].state.turn;
+            let policy = &mut *self.config.rollout[mover];
+            let edges = self.nodes[node].expand(roll, policy, rng);
+            let edge = if edges.len() == 1 {
+                0
+            } else {
+                Self::pick_edge(&self.config, edges)
+            };
+            let Edge { child, action, .. } = edges[edge];
+            path.push((node, roll, edge));
+            match child {
+                Some(child) => node = child,
+                None => {
+                    let state = Self::play(&self.nodes[node].state, roll, action);
+                    let rewards = self.rollout(&state, rng);
+                    self.nodes.push(Node::new(state));
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].edges(roll)[edge].child = Some(child);
+                    break rewards;
+                }
+            }
+            roll = roll_dice(rng);
+        };
+
+        for (node, roll, edge) in path {
+            let mover = self.nodes[node].state.turn;
+            let edge = &mut self.nodes[node].edges(roll)[edge];
+            edge.visits += 1;
+            edge.reward += rewards[mover as usize];
+        }
+    }
+
+    /// Keeps only the subtree under `root`, which becomes the new root.
+    fn reroot(&mut self, root: usize) {
+        let mut old = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
+            .collect_vec();
+        // Old index of each node to move, and the node, roll and edge leading to it.
+        let mut stack: Vec<(usize, Option<[usize; 3]>)> = vec![(root, None)];
+        while let Some((index, parent)) = stack.pop() {
+            let mut node = old[index].take().expect("trees have no shared nodes");
+            let new_index = self.nodes.len();
+            if let Some([parent, roll, edge]) = parent {
+                self.nodes[parent].rolls[roll].as_mut().unwrap()[edge].child = Some(new_index);
+            }
+            for (roll, edges) in node.rolls.iter_mut().enumerate() {
+                for (edge_index, edge) in edges.iter_mut().flatten().enumerate() {
+                    if let Some(child) = edge.child.take() {
+                        stack.push((child, Some([new_index, roll, edge_index])));
+                    }
+                }
+            }
+            self.nodes.push(node);
+        }
+    }
+}
+
+impl Bot for MctsBot {
+    fn choose(
+        &mut self,
+        state: &GameState,
+        dice: u8,
+        actions: &[Action],
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        if actions.len() == 1 {
+            return 0;
+        }
+        if self.nodes.is_empty() || !same_state(&self.nodes[0].state, state) {
+            self.nodes = vec![Node::new(state.clone())];
+        }
+
+        let start = Instant::now();
+        let mut iterations = 0;
+        while match self.config.budget {
+            Budget::Iterations(max) => iterations < max.max(1),
+            Budget::Time(time) => iterations == 0 || start.elapsed() < time,
+        } {
+            self.iterate(dice, rng);
+            iterations += 1;
+        }
+
+        let edges = self.nodes[0].edges(dice);
+        let best = edges
+            .iter()
+            .max_by_key(|edge| edge.visits)
+            .and_then(|edge| edge.action)
+            .expect("there are actions to choose from");
+        actions
+            .iter()
+            .position(|action| action.piece == best.piece && action == &best)
+            .expect("the tree has the same actions")
+    }
+
+    fn new_game(&mut self, _state: &GameState) {
+        self.nodes.clear();
+    }
+
+    fn observe_move(&mut self, ply: &Ply, state: &GameState) {
+        let child = self.nodes.first_mut().and_then(|root| {
+            let edges = root.rolls[ply.dice as usize - 1].as_ref()?;
+            edges
+                .iter()
+                .find(|edge| {
+                    edge.action.map(|action| (action, action.piece))
+                        == ply.action.map(|action| (action, action.piece))
+                })?
+                .child
+        });
+        match child {
+            Some(child)
+                if self.config.reuse_tree && same_state(&self.nodes[child].state, state) =>
+            {
+                self.reroot(child)
+            }
+            _ => self.nodes.clear(),
+        }
+    }
+
+    fn game_over(&mut self, _state: &GameState) {
+        self.nodes.clear();
+    }
+
+    fn box_clone(&self) -> Box<dyn Bot> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bots::choose_random;
+    use crate::rules::RuleSet;
+    use crate::simulator::calculate_win_percentage;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_search() {
+        let mut state =
+            GameState::with_rules(Default::default(), Player::First, RuleSet::two_player());
+        state.board_mut().players[Player::First].pieces_positions = [5, 20, 0, 0];
+        let actions = state
+            .get_actions(6)
+            .into_iter()
+            .sorted_by_key(|action| (action.player as usize, action.piece))
+            .collect_vec();
+        let config = MctsConfig {
+            budget: Budget::Iterations(100),
+            rollout: all_seats(choose_random),
+            ..Default::default()
+        };
+        let choose =
+            |bot: &mut MctsBot| bot.choose(&state, 6, &actions, &mut StdRng::seed_from_u64(3));
+        let mut bot = MctsBot::new(config);
+        let choice = choose(&mut bot);
+        assert_eq!(choose(&mut MctsBot::new(bot.config.clone())), choice);
+
+        // The tree under the move played is kept for the next one.
+        let mut after = state.clone();
+        let outcome = after.apply_action(6, actions[choice]).unwrap();
+        let ply = Ply {
+            player: Player::First,
+            dice: 6,
+            action: Some(actions[choice]),
+            captured: outcome.captured,
+            next_turn: after.turn,
+        };
+        bot.observe_move(&ply, &after);
+        assert!(bot.tree_size() > 1);
+        assert!(same_state(&bot.nodes[0].state, &after));
+    }
+
+    #[test]
+    #[ignore = "plays 100 searched games, run with `cargo test --release -- --ignored`"]
+    fn test_beats_average_bot() {
+        let state = GameState::with_rules(Default::default(), Player::First, RuleSet::two_player());
+        let mut bots = all_seats(average_bot);
+        bots[Player::First] = Box::new(MctsBot::new(MctsConfig {
+            budget: Budget::Iterations(100),
+            ..Default::default()
+        }));
+        let side = Player::First.side(&state.rules);
+        let percent = calculate_win_percentage(state, bots, side, 0);
+        assert!(percent > 0.6, "won {percent}");
+    }
+}
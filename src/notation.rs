@@ -0,0 +1,317 @@
+//! One-line text notation for game states.
+//!
+//! A state is written as up to five space separated fields:
+//!
+//! ```text
+//! 12,0,0,0/39,39,39,39/-/1,0,0,0 1 2 f 2
+//! ```
+//!
+//! 1. Piece positions for every seat on the board, separated by `/`. Each
+//!    seat lists its four pieces, or is `-` when nobody sits there. Four
+//!    seats mean the standard board, six the hexagonal one.
+//! 2. The seat to move, counting from `1`.
+//! 3. Sixes rolled in a row by the player to move.
+//! 4. `t` for team play or `f` for free-for-all.
+//! 5. Seats that already finished, in order, or `-` when nobody has.
+//!
+//! The last two fields may be left out, defaulting to the board's usual
+//! rules and to the finished seats in seat order.
+//!
+//! Only the board, the empty seats and team play are written down: other
+//! house rules, such as the entry rolls or the safe spots, come back as the
+//! board's usual ones. Game records keep the full rules in a tag of their
+//! own when the notation loses them.
+
+use std::fmt::Display;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+use crate::board::{Board, PiecePosition};
+use crate::game_state::{GameState, InvalidState};
+use crate::rules::RuleSet;
+use crate::Player;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotationError {
+    /// Not between three and five fields.
+    FieldCount(usize),
+    /// Neither four nor six seats.
+    SeatCount(usize),
+    /// A seat that doesn't list exactly four pieces.
+    PieceCount {
+        seat: Player,
+        count: usize,
+    },
+    BadPosition {
+        seat: Player,
+        text: String,
+        error: ParseIntError,
+    },
+    /// A seat number that isn't on the board, or is empty.
+    BadSeat(String),
+    BadSixStreak(String),
+    BadMode(String),
+    /// The notation is well formed, but describes an impossible state.
+    Invalid(InvalidState),
+}
+
+impl Display for NotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotationError::FieldCount(count) => {
+                write!(f, "expected 3 to 5 fields, found {}", count)
+            }
+            NotationError::SeatCount(count) => {
+                write!(f, "expected 4 or 6 seats, found {}", count)
+            }
+            NotationError::PieceCount { seat, count } => {
+                write!(f, "{:?} has {} pieces instead of 4", seat, count)
+            }
+            NotationError::BadPosition { seat, text, error } => {
+                write!(f, "{:?} has a bad position {:?}: {}", seat, text, error)
+            }
+            NotationError::BadSeat(text) => write!(f, "{:?} isn't an occupied seat", text),
+            NotationError::BadSixStreak(text) => write!(f, "{:?} isn't a six streak", text),
+            NotationError::BadMode(text) => {
+                write!(f, "expected mode `t` or `f`, found {:?}", text)
+            }
+            NotationError::Invalid(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+impl From<InvalidState> for NotationError {
+    fn from(err: InvalidState) -> Self {
+        NotationError::Invalid(err)
+    }
+}
+
+fn seat_number(player: Player) -> usize {
+    player as usize + 1
+}
+
+fn parse_seat(text: &str, rules: &RuleSet) -> Result<Player, NotationError> {
+    text.parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_sub(1))
+        .and_then(|index| rules.geometry.seats().get(index).copied())
+        .filter(|&player| rules.is_seated(player))
+        .ok_or_else(|| NotationError::BadSeat(text.to_string()))
+}
+
+/// A state in notation: `Display` writes it and `FromStr` reads it back,
+/// as `GameState`'s own `Display` draws the board instead.
+#[derive(Clone)]
+pub struct Notation(pub GameState);
+
+impl Display for Notation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.to_notation())
+    }
+}
+
+impl FromStr for Notation {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GameState::from_notation(s).map(Notation)
+    }
+}
+
+impl GameState {
+    /// Reads a state written by `to_notation`.
+    pub fn from_notation(s: &str) -> Result<Self, NotationError> {
+        let fields = s.split_whitespace().collect_vec();
+        if !(3..=5).contains(&fields.len()) {
+            return Err(NotationError::FieldCount(fields.len()));
+        }
+
+        let seats = fields[0].split('/').collect_vec();
+        let mut rules = match seats.len() {
+            4 => RuleSet::classic(),
+            6 => RuleSet::six_player(),
+            count => return Err(NotationError::SeatCount(count)),
+        };
+        let geometry_seats = rules.geometry.seats();
+        let mut board = Board::default();
+        rules.seats.clear();
+        for (&player, &seat) in geometry_seats.iter().zip(&seats) {
+            if seat == "-" {
+                continue;
+            }
+            rules.seats.push(player);
+            let positions = seat
+                .split(',')
+                .map(|text| {
+                    text.parse::<PiecePosition>()
+                        .map_err(|error| NotationError::BadPosition {
+                            seat: player,
+                            text: text.to_string(),
+                            error,
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            board.players[player].pieces_positions =
+                positions
+                    .try_into()
+                    .map_err(|positions: Vec<_>| NotationError::PieceCount {
+                        seat: player,
+                        count: positions.len(),
+                    })?;
+        }
+
+        if let Some(&mode) = fields.get(3) {
+            rules.team_mode = match mode {
+                "t" => true,
+                "f" => false,
+                _ => return Err(NotationError::BadMode(mode.to_string())),
+            };
+        }
+
+        let turn = parse_seat(fields[1], &rules)?;
+        let six_rolled = fields[2]
+            .parse()
+            .map_err(|_| NotationError::BadSixStreak(fields[2].to_string()))?;
+
+        let finishing_order = match fields.get(4) {
+            Some(&"-") => Some(vec![]),
+            Some(finished) => Some(
+                finished
+                    .chars()
+                    .map(|c| parse_seat(&c.to_string(), &rules))
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => None,
+        };
+        let state = GameState::from_parts(board, turn, rules, six_rolled, finishing_order);
+        state.validate()?;
+        Ok(state)
+    }
+
+    /// The state in notation, which `from_notation` reads back.
+    pub fn to_notation(&self) -> String {
+        let seats = self
+            .rules
+            .geometry
+            .seats()
+            .iter()
+            .map(|&player| {
+                if self.rules.is_seated(player) {
+                    self.board().players[player]
+                        .pieces_positions
+                        .iter()
+                        .join(",")
+                } else {
+                    "-".to_string()
+                }
+            })
+            .join("/");
+        let finished = if self.finishing_order().is_empty() {
+            "-".to_string()
+        } else {
+            self.finishing_order()
+                .iter()
+                .map(|&player| seat_number(player))
+                .join("")
+        };
+        format!(
+            "{} {} {} {} {}",
+            seats,
+            seat_number(self.turn),
+            self.six_rolled(),
+            if self.rules.team_mode { "t" } else { "f" },
+            finished
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::positions::Geometry;
+
+    #[test]
+    fn test_round_trip() {
+        let notation = "12,0,0,39/0,0,0,0/-/1,0,0,0 2 1 f -";
+        let state = GameState::from_notation(notation).unwrap();
+        assert_eq!(state.turn, Player::Second);
+        assert_eq!(state.six_rolled(), 1);
+        assert!(!state.rules.team_mode);
+        assert!(!state.rules.is_seated(Player::Third));
+        assert_eq!(
+            state.board().players[Player::First].pieces_positions,
+            [12, 0, 0, 39]
+        );
+        assert_eq!(state.to_notation(), notation);
+        // The newtype pairs Display with FromStr.
+        let written = Notation(state.clone()).to_string();
+        assert_eq!(written, notation);
+        assert_eq!(
+            written.parse::<Notation>().unwrap().0.to_notation(),
+            notation
+        );
+
+        assert_eq!(
+            GameState::default().to_notation(),
+            "0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 1 0 t -"
+        );
+        let six = GameState::from_notation("0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 1 0")
+            .unwrap();
+        assert_eq!(six.rules.geometry, Geometry::Hexagonal);
+
+        let finished = GameState::from_notation("12,0,0,0/39,39,39,39/-/1,0,0,0 1 2").unwrap();
+        assert_eq!(finished.finishing_order(), [Player::Second]);
+
+        // Display still draws the board, and house rules beyond the seats
+        // and teams don't survive the notation.
+        assert!(state.to_string().lines().count() > 1);
+        let mut house = state.clone();
+        house.rules = std::sync::Arc::new(RuleSet {
+            entry_rolls: vec![6],
+            ..(*state.rules).clone()
+        });
+        let parsed = GameState::from_notation(&house.to_notation()).unwrap();
+        assert_eq!(parsed.rules.entry_rolls, [1, 6]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let parse = |s: &str| GameState::from_notation(s).err().unwrap();
+        assert_eq!(
+            parse("0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 1"),
+            NotationError::FieldCount(2)
+        );
+        assert_eq!(
+            parse("0,0,0,0/0,0,0,0/0,0,0,0 1 0"),
+            NotationError::SeatCount(3)
+        );
+        assert!(matches!(
+            parse("0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 1 0"),
+            NotationError::PieceCount {
+                seat: Player::First,
+                count: 3
+            }
+        ));
+        assert_eq!(
+            parse("0,0,0,0/0,0,0,0/-/0,0,0,0 3 0"),
+            NotationError::BadSeat("3".to_string())
+        );
+        assert_eq!(
+            parse("0,0,0,41/0,0,0,0/0,0,0,0/0,0,0,0 1 0"),
+            NotationError::Invalid(InvalidState::PositionOutOfRange {
+                player: Player::First,
+                piece: 3,
+                position: 41
+            })
+        );
+        // Second's starting square is 10 squares along First's track.
+        assert!(matches!(
+            parse("10,0,0,0/1,0,0,0/0,0,0,0/0,0,0,0 1 0"),
+            NotationError::Invalid(InvalidState::SharedSquare { .. })
+        ));
+    }
+}
@@ -1,6 +1,11 @@
+use std::iter;
+use std::ops::RangeInclusive;
+
+use crate::board::{PieceLocation, PiecePosition};
 use crate::Player;
-use enum_map::{enum_map, EnumMap};
+use itertools::Itertools;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 
 const ORIGINAL_POSITIONS: [(i8, i8); 40] = [
     (4, 3),
@@ -47,25 +52,225 @@ const ORIGINAL_POSITIONS: [(i8, i8); 40] = [
 
 pub const SAFE_SPOTS: [(i8, i8); 4] = [(4, -1), (1, 4), (-4, 1), (-1, -4)];
 
-fn rotated(pos: [(i8, i8); 40]) -> [(i8, i8); 40] {
-    let mut v = [(0, 0); 40];
-    for (i, &(x, y)) in pos.iter().enumerate() {
-        v[i] = (-y, x);
-    }
-    v
+/// Cells in each of the hexagonal board's arms, counting from the centre.
+const HEX_ARM_LENGTH: i8 = 5;
+
+fn rotate((x, y): PieceLocation) -> PieceLocation {
+    (-y, x)
+}
+
+/// Rotates axial hex coordinates by 60 degrees.
+fn hex_rotate((q, r): PieceLocation) -> PieceLocation {
+    (-r, q + r)
+}
+
+fn rotated(pos: &[PieceLocation]) -> Vec<PieceLocation> {
+    pos.iter().copied().map(rotate).collect()
+}
+
+fn hex_rotated(pos: &[PieceLocation]) -> Vec<PieceLocation> {
+    pos.iter().copied().map(hex_rotate).collect()
+}
+
+/// First player's path on the hexagonal board, in axial coordinates.
+///
+/// It follows the same layout as the standard board: the yard, then the
+/// outer lanes of every arm starting next to our own, and finally the
+/// middle lane of our arm up to the centre.
+fn hex_original_positions() -> Vec<PieceLocation> {
+    let l = HEX_ARM_LENGTH;
+    // Loop cells of one arm, starting at the corner it shares with the
+    // previous arm and going out and back in.
+    let arm = (2..=l)
+        .map(|q| (q, -1))
+        .chain([(l, 0)])
+        .chain((2..=l).rev().map(|q| (q, 1)))
+        .collect_vec();
+    let other_arms = iter::successors(Some(hex_rotated(&arm)), |arm| Some(hex_rotated(arm)))
+        .take(5)
+        .flatten();
+
+    iter::once((l - 2, 2))
+        .chain(arm[l as usize + 1..].iter().copied())
+        .chain(other_arms)
+        .chain(arm[..l as usize].iter().copied())
+        .chain((1..l).rev().map(|q| (q, 0)))
+        .collect()
+}
+
+fn all_rotations(
+    original: Vec<PieceLocation>,
+    rotate: fn(&[PieceLocation]) -> Vec<PieceLocation>,
+    count: usize,
+) -> Vec<Vec<PieceLocation>> {
+    iter::successors(Some(original), |pos| Some(rotate(pos)))
+        .take(count)
+        .collect()
+}
+
+/// Numbers the squares pieces can meet on, leaving out the yard and the
+/// last place, and gives the number of each player's positions.
+fn numbered_squares(positions: &[Vec<PieceLocation>]) -> Vec<Vec<u8>> {
+    let squares = positions
+        .iter()
+        .flat_map(|track| &track[1..track.len() - 1])
+        .unique()
+        .collect_vec();
+    assert!(squares.len() <= MAX_SQUARES);
+    positions
+        .iter()
+        .map(|track| {
+            track
+                .iter()
+                .enumerate()
+                .map(|(position, location)| {
+                    if position == 0 || position == track.len() - 1 {
+                        NO_SQUARE
+                    } else {
+                        squares
+                            .iter()
+                            .position(|&square| square == location)
+                            .unwrap() as u8
+                    }
+                })
+                .collect()
+        })
+        .collect()
 }
 
 lazy_static! {
-    static ref FIRST: [(i8, i8); 40] = ORIGINAL_POSITIONS;
-    static ref SECOND: [(i8, i8); 40] = rotated(*FIRST);
-    static ref THIRD: [(i8, i8); 40] = rotated(*SECOND);
-    static ref FOURTH: [(i8, i8); 40] = rotated(*THIRD);
-    pub static ref POSITIONS: EnumMap<Player, [(i8, i8); 40]> = enum_map! {
-        Player::First => *FIRST,
-        Player::Second => *SECOND,
-        Player::Third => *THIRD,
-        Player::Fourth => *FOURTH,
-    };
+    static ref STANDARD_POSITIONS: Vec<Vec<PieceLocation>> =
+        all_rotations(ORIGINAL_POSITIONS.to_vec(), rotated, 4);
+    static ref HEXAGONAL_POSITIONS: Vec<Vec<PieceLocation>> =
+        all_rotations(hex_original_positions(), hex_rotated, 6);
+    static ref STANDARD_SQUARES: Vec<Vec<u8>> = numbered_squares(&STANDARD_POSITIONS);
+    static ref HEXAGONAL_SQUARES: Vec<Vec<u8>> = numbered_squares(&HEXAGONAL_POSITIONS);
+    static ref HEXAGONAL_SAFE_SPOTS: Vec<PieceLocation> =
+        all_rotations(vec![(HEX_ARM_LENGTH - 1, -1)], hex_rotated, 6).concat();
 }
 
+/// Most squares pieces can meet on, on any board.
+pub const MAX_SQUARES: usize = 80;
+
+/// Square number of the positions pieces can't meet on.
+pub const NO_SQUARE: u8 = u8::MAX;
+
+/// Last place on the standard board.
 pub const LAST_PLACE: u8 = ORIGINAL_POSITIONS.len() as u8 - 1;
+
+/// Shape of the board a game is played on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Geometry {
+    /// The usual cross-shaped board for up to four players.
+    #[default]
+    Standard,
+    /// A six-armed board for up to six players, in axial hex coordinates.
+    Hexagonal,
+}
+
+impl Geometry {
+    /// Seats the board has room for, in turn order.
+    pub fn seats(&self) -> &'static [Player] {
+        const ALL: [Player; 6] = [
+            Player::First,
+            Player::Second,
+            Player::Third,
+            Player::Fourth,
+            Player::Fifth,
+            Player::Sixth,
+        ];
+        match self {
+            Geometry::Standard => &ALL[..4],
+            Geometry::Hexagonal => &ALL,
+        }
+    }
+
+    /// `location` turned around the centre by `seats` seats, which takes
+    /// each seat's track onto the track of the seat that many places later.
+    pub fn rotate(&self, location: PieceLocation, seats: usize) -> PieceLocation {
+        let rotate = match self {
+            Geometry::Standard => rotate,
+            Geometry::Hexagonal => hex_rotate,
+        };
+        (0..seats % self.seats().len()).fold(location, |location, _| rotate(location))
+    }
+
+    /// Every location a player's pieces go through, from the yard at `0` to
+    /// the last place.
+    pub fn positions(&self, player: Player) -> &'static [PieceLocation] {
+        let positions: &'static Vec<_> = match self {
+            Geometry::Standard => &STANDARD_POSITIONS,
+            Geometry::Hexagonal => &HEXAGONAL_POSITIONS,
+        };
+        positions
+            .get(player as usize)
+            .unwrap_or_else(|| panic!("{:?} has no seat on a {:?} board", player, self))
+    }
+
+    /// Square each of a player's positions is on, numbered from `0` up to
+    /// `MAX_SQUARES` and the same for every player on that square, or
+    /// `NO_SQUARE` for the yard and the last place.
+    pub fn squares(&self, player: Player) -> &'static [u8] {
+        let squares: &'static Vec<_> = match self {
+            Geometry::Standard => &STANDARD_SQUARES,
+            Geometry::Hexagonal => &HEXAGONAL_SQUARES,
+        };
+        squares
+            .get(player as usize)
+            .unwrap_or_else(|| panic!("{:?} has no seat on a {:?} board", player, self))
+    }
+
+    pub fn last_place(&self) -> PiecePosition {
+        match self {
+            Geometry::Standard => LAST_PLACE,
+            Geometry::Hexagonal => HEXAGONAL_POSITIONS[0].len() as u8 - 1,
+        }
+    }
+
+    /// Positions only the owner can step on before reaching the last place.
+    pub fn home_column(&self) -> RangeInclusive<PiecePosition> {
+        let last = self.last_place();
+        last - 3..=last - 1
+    }
+
+    pub fn safe_spots(&self) -> Vec<PieceLocation> {
+        match self {
+            Geometry::Standard => SAFE_SPOTS.to_vec(),
+            Geometry::Hexagonal => HEXAGONAL_SAFE_SPOTS.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::Geometry;
+
+    #[test]
+    fn test_hexagonal_tracks() {
+        let geometry = Geometry::Hexagonal;
+        let distance = |(q1, r1): (i8, i8), (q2, r2): (i8, i8)| {
+            let (dq, dr) = (q1 - q2, r1 - r2);
+            (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+        };
+        for &player in geometry.seats() {
+            let positions = geometry.positions(player);
+            assert_eq!(positions.len(), geometry.last_place() as usize + 1);
+            assert!(positions.iter().all_unique());
+            assert!(positions[1..]
+                .windows(2)
+                .all(|step| distance(step[0], step[1]) == 1));
+        }
+
+        let shared = geometry
+            .seats()
+            .iter()
+            .flat_map(|&player| {
+                geometry.positions(player)[1..geometry.last_place() as usize - 3].to_vec()
+            })
+            .unique()
+            .count();
+        assert_eq!(shared, 54);
+    }
+}
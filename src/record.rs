@@ -0,0 +1,416 @@
+//! Text records of whole games, in the spirit of chess PGN.
+//!
+//! A record starts with `[Name "value"]` tags, followed by the plies. Each
+//! ply is the dice roll and the action played, as in `6/1a:0-1` for seat 1
+//! moving its first piece from the yard to its starting square, or `3/-`
+//! when there was nothing to play. Plies may be numbered like `12.` and
+//! followed by a `{comment}`, with `\}` and `\\` for braces and backslashes
+//! inside it.
+//!
+//! ```text
+//! [Blue "Alice"]
+//! [Yellow "average_bot"]
+//! [Position "0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 1 0 t -"]
+//!
+//! 1. 6/1a:0-1 {Straight out} 2. 4/1a:1-5 3. 2/-
+//! ```
+//!
+//! The `Position` tag holds the starting position in `notation`, and
+//! `Rules` holds the rule set as JSON when the notation alone doesn't
+//! imply it.
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use itertools::Itertools;
+
+use crate::board::{Action, IllegalMove, Side};
+use crate::game_state::GameState;
+use crate::history::GameHistory;
+use crate::notation::NotationError;
+use crate::Player;
+
+const POSITION_TAG: &str = "Position";
+const RULES_TAG: &str = "Rules";
+
+/// A roll of the dice as written in a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedPly {
+    pub dice: u8,
+    /// Action played, or `None` for a pass.
+    pub action: Option<Action>,
+    pub comment: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct GameRecord {
+    /// Metadata such as player names, bots or the seed, in order.
+    pub tags: Vec<(String, String)>,
+    pub start: GameState,
+    pub plies: Vec<RecordedPly>,
+}
+
+#[derive(Debug)]
+pub enum RecordError {
+    /// A line in the header that isn't a `[Name "value"]` tag.
+    BadTag(String),
+    BadPosition(NotationError),
+    BadRules(serde_json::Error),
+    /// A ply that can't be read, counting from `0`.
+    BadPly {
+        ply: usize,
+        text: String,
+    },
+    UnterminatedComment,
+    /// A ply that can't be played in the position it comes up in.
+    IllegalPly {
+        ply: usize,
+        error: IllegalMove,
+    },
+    /// A pass recorded when there was something to play.
+    BadPass(usize),
+}
+
+impl Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordError::BadTag(line) => write!(f, "bad tag line {:?}", line),
+            RecordError::BadPosition(err) => write!(f, "bad starting position: {}", err),
+            RecordError::BadRules(err) => write!(f, "bad rules: {}", err),
+            RecordError::BadPly { ply, text } => write!(f, "ply {}: can't read {:?}", ply, text),
+            RecordError::UnterminatedComment => write!(f, "comment is never closed"),
+            RecordError::IllegalPly { ply, error } => write!(f, "ply {}: {}", ply, error),
+            RecordError::BadPass(ply) => write!(f, "ply {}: passed with actions available", ply),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_comment(comment: &str) -> String {
+    comment.replace('\\', "\\\\").replace('}', "\\}")
+}
+
+/// Splits the text after a comment's `{` into the unescaped comment and
+/// what follows its closing `}`.
+fn split_comment(text: &str) -> Option<(String, &str)> {
+    let mut comment = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => comment.push(chars.next()?.1),
+            '}' => return Some((comment, &text[i + 1..])),
+            c => comment.push(c),
+        }
+    }
+    None
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next()?),
+            '"' => return None,
+            c => unescaped.push(c),
+        }
+    }
+    Some((name.to_string(), unescaped))
+}
+
+pub(crate) fn format_action(action: &Action) -> String {
+    format!(
+        "{}{}:{}-{}",
+        action.player as usize + 1,
+        (b'a' + action.piece) as char,
+        action.from,
+        action.to
+    )
+}
+
+fn parse_action(text: &str) -> Option<Action> {
+    let (piece, positions) = text.split_once(':')?;
+    let mut piece_chars = piece.chars();
+    let seat = piece_chars.next()?.to_digit(10)?.checked_sub(1)?;
+    let player = enum_iterator::all::<Player>().nth(seat as usize)?;
+    let piece = piece_chars.next().filter(|c| ('a'..='d').contains(c))? as u8 - b'a';
+    if piece_chars.next().is_some() {
+        return None;
+    }
+    let (from, to) = positions.split_once('-')?;
+    Some(Action {
+        player,
+        piece,
+        from: from.parse().ok()?,
+        to: to.parse().ok()?,
+    })
+}
+
+fn parse_ply(ply: usize, text: &str) -> Result<RecordedPly, RecordError> {
+    let bad_ply = || RecordError::BadPly {
+        ply,
+        text: text.to_string(),
+    };
+    let (dice, action) = text.split_once('/').ok_or_else(bad_ply)?;
+    let dice = dice.parse().ok().filter(|dice| (1..=6).contains(dice));
+    let action = match action {
+        "-" => None,
+        action => Some(parse_action(action).ok_or_else(bad_ply)?),
+    };
+    Ok(RecordedPly {
+        dice: dice.ok_or_else(bad_ply)?,
+        action,
+        comment: None,
+    })
+}
+
+/// How a game ended, for the `Result` tag.
+pub fn result_tag(state: &GameState) -> String {
+    match state.winner() {
+        Some(Side::Team(team)) => format!("{:?}", team),
+        Some(Side::Player(player)) => format!("{:?}", player),
+        None => "*".to_string(),
+    }
+}
+
+impl GameRecord {
+    pub fn new(start: GameState) -> Self {
+        Self {
+            tags: vec![],
+            start,
+            plies: vec![],
+        }
+    }
+
+    /// Records the plies that led to the current state of `history`.
+    pub fn from_history(history: &GameHistory) -> Self {
+        let mut record = Self::new(history.initial().clone());
+        record.plies = history.plies()[..history.position()]
+            .iter()
+            .map(|ply| RecordedPly {
+                dice: ply.dice,
+                action: ply.action,
+                comment: None,
+            })
+            .collect();
+        record.set_tag("Result", &result_tag(history.current()));
+        record
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Plays the game again from the start, checking every ply is legal.
+    pub fn replay(&self) -> Result<GameHistory, RecordError> {
+        let mut history = GameHistory::new(self.start.clone());
+        for (i, ply) in self.plies.iter().enumerate() {
+            let illegal = |error| RecordError::IllegalPly { ply: i, error };
+            // The recorded action itself, as `roll` only offers one of the
+            // pieces sharing a square.
+            match ply.action {
+                None if !history.current().get_actions(ply.dice).is_empty() => {
+                    return Err(RecordError::BadPass(i))
+                }
+                None => history.roll(ply.dice, |_, _| 0).map_err(illegal)?,
+                Some(action) => history.apply_action(ply.dice, action).map_err(illegal)?,
+            };
+        }
+        Ok(history)
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = RecordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tags = vec![];
+        let mut lines = s.lines().peekable();
+        while let Some(line) = lines.next_if(|line| line.trim().is_empty() || line.starts_with('['))
+        {
+            let line = line.trim();
+            if !line.is_empty() {
+                tags.push(parse_tag(line).ok_or_else(|| RecordError::BadTag(line.to_string()))?);
+            }
+        }
+
+        let take_tag = |tags: &mut Vec<(String, String)>, name| {
+            let index = tags.iter().position(|(tag, _)| tag == name)?;
+            Some(tags.remove(index).1)
+        };
+        let mut start = match take_tag(&mut tags, POSITION_TAG) {
+            Some(position) => {
+                GameState::from_notation(&position).map_err(RecordError::BadPosition)?
+            }
+            None => GameState::default(),
+        };
+        if let Some(rules) = take_tag(&mut tags, RULES_TAG) {
+            start.rules = Arc::new(serde_json::from_str(&rules).map_err(RecordError::BadRules)?);
+            start
+                .validate()
+                .map_err(|err| RecordError::BadPosition(err.into()))?;
+        }
+
+        let movetext = lines.join("\n");
+        let mut plies: Vec<RecordedPly> = vec![];
+        let mut rest = movetext.as_str();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(comment) = rest.strip_prefix('{') {
+                let (comment, after) =
+                    split_comment(comment).ok_or(RecordError::UnterminatedComment)?;
+                let ply = plies.len();
+                let last = plies.last_mut().ok_or_else(|| RecordError::BadPly {
+                    ply,
+                    text: format!("{{{}}}", comment),
+                })?;
+                last.comment = Some(comment.trim().to_string());
+                rest = after;
+                continue;
+            }
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '{')
+                .unwrap_or(rest.len());
+            let (token, after) = rest.split_at(end);
+            rest = after;
+            let is_number = token
+                .strip_suffix('.')
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            if !is_number {
+                plies.push(parse_ply(plies.len(), token)?);
+            }
+        }
+
+        Ok(Self { tags, start, plies })
+    }
+}
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+        }
+        let position = self.start.to_notation();
+        writeln!(f, "[{} \"{}\"]", POSITION_TAG, position)?;
+        let implied = GameState::from_notation(&position).expect("notation always parses back");
+        if implied.rules != self.start.rules {
+            let rules = serde_json::to_string(&*self.start.rules).unwrap();
+            writeln!(f, "[{} \"{}\"]", RULES_TAG, escape(&rules))?;
+        }
+        writeln!(f)?;
+
+        let mut line = String::new();
+        for (i, ply) in self.plies.iter().enumerate() {
+            let mut text = format!(
+                "{}. {}/{}",
+                i + 1,
+                ply.dice,
+                ply.action.as_ref().map_or("-".to_string(), format_action)
+            );
+            if let Some(comment) = &ply.comment {
+                text += &format!(" {{{}}}", escape_comment(comment));
+            }
+            if !line.is_empty() && line.len() + text.len() >= 80 {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &text;
+        }
+        if !line.is_empty() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bots::average_bot;
+    use crate::dice::{DiceSource, SeededDice};
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn test_round_trip() {
+        let rules = RuleSet {
+            entry_rolls: vec![6],
+            ..RuleSet::three_player()
+        };
+        let mut history = GameHistory::new(GameState::with_rules(
+            Default::default(),
+            Player::First,
+            rules,
+        ));
+        let mut dice = SeededDice::new(0);
+        for _ in 0..200 {
+            let roll = dice.roll().unwrap();
+            history
+                .roll(roll, |state, actions| {
+                    average_bot(state, actions, dice.rng())
+                })
+                .unwrap();
+        }
+        let mut record = GameRecord::from_history(&history);
+        record.set_tag("Blue", "Alice \"the bot\"");
+        record.plies[3].comment = Some("Interesting".to_string());
+        // Closing braces and backslashes don't end the comment early.
+        record.plies[5].comment = Some(r"Not over} 7. 6/- {yet \".to_string());
+
+        let parsed: GameRecord = record.to_string().parse().unwrap();
+        assert_eq!(parsed.tag("Blue"), Some("Alice \"the bot\""));
+        assert_eq!(parsed.plies, record.plies);
+        assert_eq!(parsed.start.rules, record.start.rules);
+        let replayed = parsed.replay().unwrap();
+        assert_eq!(replayed.current().board(), history.current().board());
+    }
+
+    #[test]
+    fn test_replay_piece() {
+        // Piece b leaves the yard, then moves on by itself.
+        let record: GameRecord = "1. 6/1b:0-1 2. 3/1b:1-4".parse().unwrap();
+        let state = record.replay().unwrap().current().clone();
+        assert_eq!(
+            state.board().players[Player::First].pieces_positions,
+            [0, 4, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_illegal_ply() {
+        let record: GameRecord = "1. 6/1a:0-1 2. 3/1a:1-4 3. 2/1b:0-2".parse().unwrap();
+        assert_eq!(record.plies.len(), 3);
+        assert!(matches!(
+            record.replay(),
+            Err(RecordError::IllegalPly { ply: 2, .. })
+        ));
+        assert!(matches!(
+            "1. 7/-".parse::<GameRecord>(),
+            Err(RecordError::BadPly { ply: 0, .. })
+        ));
+    }
+}
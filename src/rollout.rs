@@ -0,0 +1,386 @@
+//! Rollouts played in batches that keep every thread busy.
+//!
+//! Each thread of the pool takes the next game left in the batch as soon as
+//! it's done with the last, so threads never wait for each other until the
+//! batch runs out, and adds its results up itself, only combining them with
+//! the other threads' at the end. Game `n` of a position always gets the
+//! dice derived from its seed and `n`, so the stats don't depend on which
+//! thread played what.
+//!
+//! An engine can be given a pool of its own, so that embedding it doesn't
+//! take every core, and a deadline and a cancel token that stop it between
+//! games.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+use crate::board::{Action, Side};
+use crate::bots::Bots;
+use crate::dice::{derive_seed, DiceSource, SeededDice, SplitDice};
+use crate::game_state::GameState;
+use crate::simulator::simulate_to_finish;
+use crate::utils::{action_rollouts, GameResult, RolloutDice, Stats};
+
+/// Stops the rollouts using it, from any thread. Clones share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Games from a position, played by `bots` until a side wins.
+#[derive(Clone)]
+pub struct Rollouts {
+    pub state: GameState,
+    /// Side whose wins are counted.
+    pub side: Side,
+    pub bots: Bots,
+    pub rollout_dice: RolloutDice,
+    pub seed: u64,
+}
+
+impl Rollouts {
+    pub fn new(
+        state: GameState,
+        side: Side,
+        bots: Bots,
+        rollout_dice: RolloutDice,
+        seed: u64,
+    ) -> Self {
+        Self {
+            state,
+            side,
+            bots,
+            rollout_dice,
+            seed,
+        }
+    }
+
+    /// Plays game `game`, with dice that only depend on the seed and
+    /// `game`. Unless `rollout_dice` is the default, each seat gets its own
+    /// rolls, which don't depend on what the bots choose.
+    pub fn play(&self, game: u64) -> GameResult {
+        let won = if self.rollout_dice == RolloutDice::default() {
+            self.play_with(SeededDice::new(derive_seed(self.seed, game)))
+        } else {
+            let antithetic = self.rollout_dice.antithetic;
+            let pair = if antithetic { game / 2 } else { game };
+            let rolls = derive_seed(self.seed, 2 * pair);
+            let choices = derive_seed(self.seed, 2 * game + 1);
+            if antithetic && game % 2 == 1 {
+                self.play_with(SplitDice::mirrored(rolls, choices))
+            } else {
+                self.play_with(SplitDice::new(rolls, choices))
+            }
+        };
+        if won {
+            GameResult::Win
+        } else {
+            GameResult::Loss
+        }
+    }
+
+    fn play_with(&self, mut dice: impl DiceSource) -> bool {
+        let mut bots = self.bots.clone();
+        simulate_to_finish(&mut self.state.clone(), &mut bots, &mut dice) == self.side
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RolloutConfig {
+    /// Games per second to keep under, or `None` to play as fast as the
+    /// threads can.
+    pub target_rate: Option<f64>,
+    /// Stops the games not yet started when cancelled.
+    pub cancel: CancelToken,
+    /// Time after which no more games are started.
+    pub deadline: Option<Instant>,
+    /// Pool the games are played on, or `None` for the pool the engine is
+    /// run from, which is rayon's global pool unless the caller is in
+    /// another.
+    pub pool: Option<Arc<ThreadPool>>,
+}
+
+impl RolloutConfig {
+    /// The same config, playing on a pool of its own with `threads`
+    /// threads.
+    pub fn with_threads(self, threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build()?;
+        Ok(Self {
+            pool: Some(Arc::new(pool)),
+            ..self
+        })
+    }
+}
+
+/// Games played by an engine and how long they took.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RolloutMetrics {
+    pub games: u64,
+    /// Time spent running batches, waits for the target rate included.
+    pub elapsed: Duration,
+    /// Batches that stopped early, cancelled or out of time.
+    pub stopped: u64,
+}
+
+impl RolloutMetrics {
+    pub fn games_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.games as f64 / seconds
+    }
+}
+
+#[derive(Default)]
+pub struct RolloutEngine {
+    pub config: RolloutConfig,
+    metrics: Mutex<RolloutMetrics>,
+}
+
+impl RolloutEngine {
+    pub fn new(config: RolloutConfig) -> Self {
+        Self {
+            config,
+            metrics: Mutex::default(),
+        }
+    }
+
+    /// Whether the engine was cancelled or is past its deadline, so that
+    /// it won't start any more games.
+    pub fn is_stopped(&self) -> bool {
+        self.config.cancel.is_cancelled()
+            || self
+                .config
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Plays games `range` of each of `batches` on the engine's pool, all at
+    /// once, returning the stats of each. Games that hadn't started when the
+    /// engine was stopped are left out; the ones under way are finished.
+    pub fn run(&self, batches: &[(&Rollouts, Range<u64>)]) -> Vec<Stats> {
+        match &self.config.pool {
+            Some(pool) => pool.install(|| self.run_here(batches)),
+            None => self.run_here(batches),
+        }
+    }
+
+    /// `run`, on the pool the caller is in.
+    fn run_here(&self, batches: &[(&Rollouts, Range<u64>)]) -> Vec<Stats> {
+        // Where each batch starts among all the games.
+        let mut offsets = vec![0];
+        for (_, range) in batches {
+            offsets.push(offsets.last().unwrap() + range.end.saturating_sub(range.start));
+        }
+        self.play_games(
+            *offsets.last().unwrap(),
+            || vec![Stats::default(); batches.len()],
+            |stats, game| {
+                let batch = offsets.partition_point(|&offset| offset <= game) - 1;
+                let (rollouts, range) = &batches[batch];
+                stats[batch].add(rollouts.play(range.start + game - offsets[batch]));
+            },
+            |mut total, stats| {
+                for (total, stats) in total.iter_mut().zip(stats) {
+                    total.merge(stats);
+                }
+                total
+            },
+        )
+    }
+
+    /// Games `range` of `rollouts` on the engine's pool, in game order.
+    /// Games that hadn't started when the engine was stopped are left out,
+    /// so the results are always those of the first games of `range`.
+    pub fn results(&self, rollouts: &Rollouts, range: Range<u64>) -> Vec<GameResult> {
+        let play = || {
+            let total = range.end.saturating_sub(range.start);
+            let mut results = self.play_games(
+                total,
+                Vec::new,
+                |results, game| results.push((game, rollouts.play(range.start + game))),
+                |mut all, results| {
+                    all.extend(results);
+                    all
+                },
+            );
+            results.sort_unstable_by_key(|&(game, _)| game);
+            results.into_iter().map(|(_, result)| result).collect()
+        };
+        match &self.config.pool {
+            Some(pool) => pool.install(play),
+            None => play(),
+        }
+    }
+
+    /// Stats from `games` rollouts of each action `dice` allows, played
+    /// all at once. Once the engine is stopped, the stats are those of the
+    /// games played so far.
+    pub fn stats_per_action(
+        &self,
+        state: GameState,
+        dice: u8,
+        side: Side,
+        bots: Bots,
+        games: u64,
+        seed: u64,
+    ) -> HashMap<Action, Stats> {
+        let actions = action_rollouts(state, dice, side, bots, RolloutDice::default(), seed);
+        let batches = actions
+            .iter()
+            .map(|(_, rollouts)| (rollouts, 0..games))
+            .collect::<Vec<_>>();
+        let stats = self.run(&batches);
+        actions
+            .iter()
+            .map(|(action, _)| *action)
+            .zip(stats)
+            .collect()
+    }
+
+    /// Plays games `0..total` on the pool the caller is in, each thread
+    /// taking the next game as soon as it's done with the last and adding
+    /// it to an accumulator of its own, which are merged at the end.
+    fn play_games<T: Send>(
+        &self,
+        total: u64,
+        init: impl Fn() -> T + Sync + Send,
+        play: impl Fn(&mut T, u64) + Sync + Send,
+        merge: impl Fn(T, T) -> T + Sync + Send,
+    ) -> T {
+        let start = Instant::now();
+        let next = AtomicU64::new(0);
+        let played = AtomicU64::new(0);
+
+        let merged = (0..rayon::current_num_threads())
+            .into_par_iter()
+            .map(|_| {
+                let mut acc = init();
+                loop {
+                    if self.is_stopped() {
+                        break;
+                    }
+                    let game = next.fetch_add(1, Ordering::Relaxed);
+                    if game >= total {
+                        break;
+                    }
+                    if let Some(rate) = self.config.target_rate {
+                        let mut due = start + Duration::from_secs_f64(game as f64 / rate);
+                        if let Some(deadline) = self.config.deadline {
+                            due = due.min(deadline);
+                        }
+                        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+                        if self.is_stopped() {
+                            break;
+                        }
+                    }
+                    play(&mut acc, game);
+                    played.fetch_add(1, Ordering::Relaxed);
+                }
+                acc
+            })
+            .reduce(&init, &merge);
+
+        let played = played.into_inner();
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.games += played;
+        metrics.elapsed += start.elapsed();
+        if played < total {
+            metrics.stopped += 1;
+        }
+        merged
+    }
+
+    /// Totals over every batch run so far.
+    pub fn metrics(&self) -> RolloutMetrics {
+        *self.metrics.lock().unwrap()
+    }
+
+    pub fn reset_metrics(&self) {
+        *self.metrics.lock().unwrap() = RolloutMetrics::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bots::{all_seats, choose_random};
+    use crate::Player;
+
+    #[test]
+    fn test_engine() {
+        let state = GameState::default();
+        let side = Player::First.side(&state.rules);
+        let rollouts = Rollouts::new(
+            state.clone(),
+            side,
+            all_seats(choose_random),
+            RolloutDice::default(),
+            7,
+        );
+        let engine = RolloutEngine::default();
+        let stats = engine.run(&[(&rollouts, 0..30), (&rollouts, 30..60)]);
+        // The same games as played one by one, in two batches.
+        let wins = engine
+            .results(&rollouts, 0..60)
+            .into_iter()
+            .map(|result| matches!(result, GameResult::Win) as u64)
+            .collect::<Vec<_>>();
+        assert_eq!(wins.len(), 60);
+        assert_eq!(stats[0].wins(), wins[..30].iter().sum::<u64>());
+        assert_eq!(stats[1].wins(), wins[30..].iter().sum::<u64>());
+        let metrics = engine.metrics();
+        assert_eq!(metrics.games, 120);
+        assert!(metrics.games_per_second() > 0.0);
+
+        let paced = RolloutEngine::new(RolloutConfig {
+            target_rate: Some(200.0),
+            ..Default::default()
+        });
+        paced.run(&[(&rollouts, 0..10)]);
+        assert!(paced.metrics().elapsed >= Duration::from_millis(45));
+
+        let cancelled = RolloutEngine::default();
+        cancelled.config.cancel.cancel();
+        assert_eq!(cancelled.run(&[(&rollouts, 0..10)])[0].games(), 0);
+        assert!(cancelled.results(&rollouts, 0..10).is_empty());
+        assert_eq!(cancelled.metrics().stopped, 2);
+
+        // A pool of its own, and a deadline that has already passed.
+        let config = RolloutConfig::default().with_threads(2).unwrap();
+        let pooled = RolloutEngine::new(config.clone());
+        let stats = pooled.run(&[(&rollouts, 0..30)]);
+        assert_eq!(stats[0].wins(), wins[..30].iter().sum::<u64>());
+        let results = pooled.results(&rollouts, 30..60);
+        let won = results
+            .iter()
+            .map(|result| matches!(result, GameResult::Win) as u64);
+        assert!(won.eq(wins[30..].iter().copied()));
+        assert_eq!(pooled.metrics().stopped, 0);
+        let late = RolloutEngine::new(RolloutConfig {
+            deadline: Some(Instant::now()),
+            ..config
+        });
+        assert!(late.is_stopped());
+        assert_eq!(late.run(&[(&rollouts, 0..10)])[0].games(), 0);
+    }
+}
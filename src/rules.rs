@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+use crate::board::PieceLocation;
+use crate::positions::Geometry;
+use crate::Player;
+
+/// House rules a game is played with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    /// Dice values that let a piece leave the yard.
+    pub entry_rolls: Vec<u8>,
+    /// Rolling this many sixes in a row forfeits the turn. `0` means never.
+    pub max_consecutive_sixes: u8,
+    /// Capturing an enemy piece grants another turn.
+    pub capture_bonus: bool,
+    /// Moving a piece to the last place grants another turn.
+    pub finish_bonus: bool,
+    /// Alternate seats play together as a team.
+    pub team_mode: bool,
+    /// Squares where pieces can't be captured.
+    pub safe_spots: Vec<PieceLocation>,
+    /// Seats taken by a player. Empty seats are skipped and their pieces
+    /// never appear on the board.
+    pub seats: Vec<Player>,
+    /// Board the game is played on.
+    pub geometry: Geometry,
+}
+
+impl RuleSet {
+    /// The rules the engine has always been played with.
+    pub fn classic() -> Self {
+        Self {
+            entry_rolls: vec![1, 6],
+            max_consecutive_sixes: 3,
+            capture_bonus: true,
+            finish_bonus: true,
+            team_mode: true,
+            safe_spots: Geometry::Standard.safe_spots(),
+            seats: Geometry::Standard.seats().to_vec(),
+            geometry: Geometry::Standard,
+        }
+    }
+
+    /// Six players on the hexagonal board, each on their own.
+    pub fn six_player() -> Self {
+        let geometry = Geometry::Hexagonal;
+        Self {
+            team_mode: false,
+            safe_spots: geometry.safe_spots(),
+            seats: geometry.seats().to_vec(),
+            geometry,
+            ..Self::classic()
+        }
+    }
+
+    /// Two players sitting opposite each other, each on their own.
+    pub fn two_player() -> Self {
+        Self {
+            team_mode: false,
+            seats: vec![Player::First, Player::Third],
+            ..Self::classic()
+        }
+    }
+
+    /// Three players, each on their own, leaving the last seat empty.
+    pub fn three_player() -> Self {
+        Self {
+            team_mode: false,
+            seats: vec![Player::First, Player::Second, Player::Third],
+            ..Self::classic()
+        }
+    }
+
+    /// The first player of `seats` the board has no seat for, if any.
+    pub fn unknown_seat(&self) -> Option<Player> {
+        let board_seats = self.geometry.seats();
+        self.seats
+            .iter()
+            .copied()
+            .find(|player| !board_seats.contains(player))
+    }
+
+    pub fn is_seated(&self, player: Player) -> bool {
+        self.seats.contains(&player)
+    }
+
+    pub fn can_enter(&self, dice: u8) -> bool {
+        self.entry_rolls.contains(&dice)
+    }
+
+    pub fn is_safe(&self, location: PieceLocation) -> bool {
+        self.safe_spots.contains(&location)
+    }
+
+    /// Whether a turn that already had `six_rolled` sixes in a row is
+    /// forfeited by rolling one more.
+    pub fn sixes_exhausted(&self, six_rolled: u8) -> bool {
+        self.max_consecutive_sixes != 0
+            && six_rolled.saturating_add(1) >= self.max_consecutive_sixes
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::classic()
+    }
+}
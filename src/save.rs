@@ -0,0 +1,181 @@
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game_state::{GameState, InvalidState, SavedState};
+
+/// Version written to new save files.
+pub const SAVE_VERSION: u64 = 1;
+
+#[derive(Serialize)]
+struct SaveFileRef<'a> {
+    version: u64,
+    state: &'a GameState,
+}
+
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SaveFile {
+    #[allow(dead_code)]
+    version: u64,
+    state: SavedState,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    /// The file isn't JSON, or doesn't follow the save file schema.
+    Json(serde_json::Error),
+    MissingVersion,
+    UnsupportedVersion(u64),
+    /// The file is well formed, but the game in it can't happen.
+    Invalid(InvalidState),
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "couldn't access save file: {}", err),
+            SaveError::Json(err) => write!(f, "malformed save file: {}", err),
+            SaveError::MissingVersion => write!(f, "save file has no version"),
+            SaveError::UnsupportedVersion(version) => write!(
+                f,
+                "save file version {} isn't supported, expected {}",
+                version, SAVE_VERSION
+            ),
+            SaveError::Invalid(err) => write!(f, "invalid saved game: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveError::Io(err) => Some(err),
+            SaveError::Json(err) => Some(err),
+            SaveError::Invalid(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(err: serde_json::Error) -> Self {
+        SaveError::Json(err)
+    }
+}
+
+impl From<InvalidState> for SaveError {
+    fn from(err: InvalidState) -> Self {
+        SaveError::Invalid(err)
+    }
+}
+
+pub fn to_string(state: &GameState) -> String {
+    serde_json::to_string_pretty(&SaveFileRef {
+        version: SAVE_VERSION,
+        state,
+    })
+    .expect("game states always serialize")
+}
+
+pub fn from_str(s: &str) -> Result<GameState, SaveError> {
+    match serde_json::from_str::<SaveHeader>(s)?.version {
+        None => return Err(SaveError::MissingVersion),
+        Some(SAVE_VERSION) => {}
+        Some(version) => return Err(SaveError::UnsupportedVersion(version)),
+    }
+    // Checked apart from the parsing, to tell invalid games from bad JSON.
+    Ok(GameState::try_from(
+        serde_json::from_str::<SaveFile>(s)?.state,
+    )?)
+}
+
+pub fn save(state: &GameState, path: impl AsRef<Path>) -> Result<(), SaveError> {
+    Ok(fs::write(path, to_string(state))?)
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<GameState, SaveError> {
+    from_str(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{board::Board, rules::RuleSet, Player};
+
+    #[test]
+    fn test_round_trip() {
+        let mut state =
+            GameState::with_rules(Board::default(), Player::Second, RuleSet::three_player());
+        state.roll(6, |_, _| 0).unwrap();
+        state.roll(6, |_, _| 0).unwrap();
+        assert_eq!(state.six_rolled(), 2);
+
+        let saved = to_string(&state);
+        assert!(!saved.contains("Fourth"));
+        let restored = from_str(&saved).unwrap();
+        assert_eq!(restored.board(), state.board());
+        assert_eq!(restored.turn, state.turn);
+        assert_eq!(restored.rules, state.rules);
+        assert_eq!(restored.six_rolled(), 2);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(matches!(from_str("{}"), Err(SaveError::MissingVersion)));
+        assert!(matches!(
+            from_str(r#"{"version": 7}"#),
+            Err(SaveError::UnsupportedVersion(7))
+        ));
+        assert!(matches!(
+            from_str(r#"{"version": 1, "state": {}}"#),
+            Err(SaveError::Json(_))
+        ));
+
+        let mut state = GameState::default();
+        state.board_mut().players[Player::First].pieces_positions[0] = 50;
+        assert!(matches!(
+            from_str(&to_string(&state)),
+            Err(SaveError::Invalid(InvalidState::PositionOutOfRange { .. }))
+        ));
+
+        // Second's starting square is 10 squares along First's track.
+        state.board_mut().players[Player::First].pieces_positions[0] = 10;
+        state.board_mut().players[Player::Second].pieces_positions[0] = 1;
+        assert!(matches!(
+            from_str(&to_string(&state)),
+            Err(SaveError::Invalid(InvalidState::SharedSquare { .. }))
+        ));
+        // Checked however the state is deserialised.
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(serde_json::from_str::<GameState>(&json).is_err());
+
+        // A truncated board isn't taken for one with Second in the yard.
+        let mut saved: serde_json::Value = serde_json::from_str(&to_string(&state)).unwrap();
+        saved["state"]["board"]
+            .as_object_mut()
+            .unwrap()
+            .remove("Second");
+        assert!(matches!(
+            from_str(&saved.to_string()),
+            Err(SaveError::Invalid(InvalidState::MissingSeat(
+                Player::Second
+            )))
+        ));
+    }
+}
@@ -1,61 +1,354 @@
 use crate::{
-    board::{Action, Team},
-    bots::Bot,
+    board::{Action, Side},
+    bots::Bots,
+    dice::DiceSource,
     game_state::GameState,
-    utils::{roll_dice, stats_calculator, stats_per_action, game_simulator_iterator},
+    history::{GameHistory, Ply},
+    record::GameRecord,
+    rollout::{RolloutEngine, Rollouts},
+    tablebase::Tablebase,
+    utils::{sample_actions, RolloutDice, SampleOptions},
     Player,
 };
-use enum_map::EnumMap;
 use itertools::Itertools;
-use rand::thread_rng;
+
+fn observe(bots: &mut Bots, ply: &Ply, state: &GameState) {
+    for &player in &state.rules.seats {
+        bots[player].observe_move(ply, state);
+    }
+}
+
+fn start_game(bots: &mut Bots, state: &GameState) {
+    for &player in &state.rules.seats {
+        bots[player].new_game(state);
+    }
+}
+
+fn end_game(bots: &mut Bots, state: &GameState) {
+    for &player in &state.rules.seats {
+        bots[player].game_over(state);
+    }
+}
+
+/// Rolls the dice and lets the bot to move play, telling every bot what
+/// happened. Returns `None` if `dice` ran out.
+fn play_turn(
+    state: &mut GameState,
+    bots: &mut Bots,
+    dice: &mut (impl DiceSource + ?Sized),
+) -> Option<Ply> {
+    let player = state.turn;
+    let roll = dice.roll_for(player)?;
+    let bot = &mut bots[player];
+    let rng = dice.rng();
+    let mut action = None;
+    let outcome = state
+        .roll(roll, |state, actions| {
+            let i = bot.choose(state, roll, actions, rng);
+            action = actions.get(i).copied();
+            i
+        })
+        .expect("bots choose one of the actions given");
+    let ply = Ply {
+        player,
+        dice: roll,
+        action,
+        captured: outcome.and_then(|outcome| outcome.captured),
+        next_turn: state.turn,
+    };
+    observe(bots, &ply, state);
+    Some(ply)
+}
 
 fn simulate(
     state: &mut GameState,
-    bots: &EnumMap<Player, Bot>,
+    bots: &mut Bots,
+    dice: &mut (impl DiceSource + ?Sized),
     max_iters: Option<u64>,
-) -> Option<Team> {
-    let mut rng = thread_rng();
-
+) -> Option<Side> {
+    start_game(bots, state);
     let mut i = 0u64;
-    while state.board.who_won().is_none() && max_iters.map_or(true, |max| i < max) {
-        let dice = roll_dice(&mut rng);
-        state.roll(dice, &bots[state.turn]);
+    while state.winner().is_none() && max_iters.is_none_or(|max| i < max) {
+        if play_turn(state, bots, dice).is_none() {
+            break;
+        }
         i += 1;
     }
 
-    state.board.who_won()
+    if state.winner().is_some() {
+        end_game(bots, state);
+    }
+    state.winner()
 }
 
-pub fn simulate_to_finish(state: &mut GameState, bots: &EnumMap<Player, Bot>) -> Team {
-    simulate(state, bots, None).unwrap()
+/// Plays until a side wins.
+///
+/// Panics if `dice` runs out of rolls first.
+pub fn simulate_to_finish(
+    state: &mut GameState,
+    bots: &mut Bots,
+    dice: &mut (impl DiceSource + ?Sized),
+) -> Side {
+    simulate(state, bots, dice, None).expect("the dice ran out before the game finished")
 }
 
-pub fn calculate_win_percentage(state: GameState, bots: EnumMap<Player, Bot>, team: Team) -> f64 {
-    let mut stats = stats_calculator(game_simulator_iterator(state, team, bots));
-    stats.nth(100).unwrap().get_percent()
+/// Plays until every player has a place, or `dice` runs out, returning the
+/// finishing order.
+///
+/// With teams, the game ends as soon as a team wins, so only the players
+/// that had finished by then are listed.
+pub fn simulate_finishing_order(
+    state: &mut GameState,
+    bots: &mut Bots,
+    dice: &mut (impl DiceSource + ?Sized),
+) -> Vec<Player> {
+    start_game(bots, state);
+    while !state.is_over() {
+        if play_turn(state, bots, dice).is_none() {
+            return state.finishing_order().to_vec();
+        }
+    }
+
+    end_game(bots, state);
+    state.finishing_order().to_vec()
+}
+
+/// Plays a game to the end, or until `dice` runs out, keeping a record of
+/// it that can be saved and replayed.
+pub fn simulate_record(
+    state: GameState,
+    bots: &mut Bots,
+    dice: &mut (impl DiceSource + ?Sized),
+) -> GameRecord {
+    start_game(bots, &state);
+    let mut history = GameHistory::new(state);
+
+    while !history.current().is_over() {
+        let Some(roll) = dice.roll_for(history.current().turn) else {
+            break;
+        };
+        let bot = &mut bots[history.current().turn];
+        let rng = dice.rng();
+        let ply = *history
+            .roll(roll, |state, actions| bot.choose(state, roll, actions, rng))
+            .expect("bots choose one of the actions given");
+        observe(bots, &ply, history.current());
+    }
+
+    if history.current().is_over() {
+        end_game(bots, history.current());
+    }
+    let mut record = GameRecord::from_history(&history);
+    for &player in &history.initial().rules.seats {
+        record.set_tag(player.name(), &bots[player].name());
+    }
+    record
+}
+
+/// Win rate of `side` over 100 games, seeded with `seed`.
+pub fn calculate_win_percentage(state: GameState, bots: Bots, side: Side, seed: u64) -> f64 {
+    calculate_win_percentage_with(&RolloutEngine::default(), state, bots, side, seed)
+        .expect("the default engine plays every game")
+}
+
+/// Like `calculate_win_percentage`, with the games played by `engine`, on
+/// its pool and only until it's stopped. `None` if it was stopped before
+/// the first game.
+pub fn calculate_win_percentage_with(
+    engine: &RolloutEngine,
+    state: GameState,
+    bots: Bots,
+    side: Side,
+    seed: u64,
+) -> Option<f64> {
+    let rollouts = Rollouts::new(state, side, bots, RolloutDice::default(), seed);
+    let stats = engine.run(&[(&rollouts, 0..100)])[0];
+    (stats.games() > 0).then(|| stats.get_percent())
 }
 
 pub fn get_ranked_actions(
     state: GameState,
     dice: u8,
-    bots: EnumMap<Player, Bot>,
-    team: Team,
+    bots: Bots,
+    side: Side,
     depth: usize,
+    seed: u64,
+) -> Vec<(Action, f64)> {
+    get_ranked_actions_with(
+        &RolloutEngine::default(),
+        state,
+        dice,
+        bots,
+        side,
+        SampleOptions::fixed(depth),
+        seed,
+    )
+}
+
+/// Actions ranked by the win rate of `side` after playing them, best
+/// first, with the rollouts played by `engine` and shared out and rolled
+/// as `options` asks. Once the engine is stopped, actions are ranked on
+/// the games played so far, and those without any are left out.
+pub fn get_ranked_actions_with(
+    engine: &RolloutEngine,
+    state: GameState,
+    dice: u8,
+    bots: Bots,
+    side: Side,
+    options: SampleOptions,
+    seed: u64,
 ) -> Vec<(Action, f64)> {
-    stats_per_action(state, dice, team, bots)
-        .nth(depth)
-        .unwrap()
+    sample_actions(engine, state, dice, side, bots, options, seed)
         .iter()
-        .map(|(&action, &stats)| (action, stats.get_percent()))
-        .sorted_by(|(_, stats1), (_, stats2)| stats2.partial_cmp(&stats1).unwrap())
+        .filter(|(_, stats)| stats.games() > 0)
+        .map(|(action, stats)| (*action, stats.get_percent()))
+        .sorted_by(|(_, stats1), (_, stats2)| stats2.partial_cmp(stats1).unwrap())
         .collect()
 }
 
+/// The action with the best win rate for `side` over 100 rollouts, or the
+/// perfect action and its chances if the position is in `tablebase`.
 pub fn get_best_action(
     state: GameState,
     dice: u8,
-    bots: EnumMap<Player, Bot>,
-    team: Team,
+    bots: Bots,
+    side: Side,
+    seed: u64,
+    tablebase: Option<&Tablebase>,
 ) -> Option<(Action, f64)> {
-    Some(*get_ranked_actions(state, dice, bots, team, 100).first()?)
+    if let Some(probed) = tablebase.and_then(|tb| tb.best_action_value(&state, dice, side)) {
+        return Some(probed);
+    }
+    let options = SampleOptions::fixed(100);
+    get_best_action_with(
+        &RolloutEngine::default(),
+        state,
+        dice,
+        bots,
+        side,
+        options,
+        seed,
+    )
+}
+
+/// The best of `get_ranked_actions_with`, such as the one left once
+/// racing has dropped the others. `None` if the engine was stopped before
+/// any action was rolled out.
+pub fn get_best_action_with(
+    engine: &RolloutEngine,
+    state: GameState,
+    dice: u8,
+    bots: Bots,
+    side: Side,
+    options: SampleOptions,
+    seed: u64,
+) -> Option<(Action, f64)> {
+    let ranked = get_ranked_actions_with(engine, state, dice, bots, side, options, seed);
+    Some(*ranked.first()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bots::{all_seats, average_bot, choose_random, Bot};
+    use crate::dice::SeededDice;
+    use crate::rollout::{CancelToken, RolloutConfig};
+    use crate::rules::RuleSet;
+    use rand::RngCore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Counts the hook calls, shared between its clones.
+    #[derive(Clone, Default)]
+    struct Counter(Arc<[AtomicUsize; 3]>);
+
+    impl Bot for Counter {
+        fn choose(&mut self, _: &GameState, _: u8, _: &[Action], _: &mut dyn RngCore) -> usize {
+            0
+        }
+
+        fn new_game(&mut self, _: &GameState) {
+            self.0[0].fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn observe_move(&mut self, _: &Ply, _: &GameState) {
+            self.0[1].fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn game_over(&mut self, _: &GameState) {
+            self.0[2].fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn box_clone(&self) -> Box<dyn Bot> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn test_bot_hooks() {
+        let counter = Counter::default();
+        let state = GameState::with_rules(Default::default(), Player::First, RuleSet::two_player());
+        // Only the seated players need a bot.
+        let mut bots = Bots::seated(&state.rules, |player| match player {
+            Player::First => Box::new(counter.clone()),
+            _ => Box::new(average_bot),
+        });
+        assert_eq!(bots[Player::First].name(), "Counter");
+
+        let record = simulate_record(state, &mut bots, &mut SeededDice::new(1));
+        assert!(record.replay().unwrap().current().is_over());
+        let counts = counter
+            .0
+            .each_ref()
+            .map(|count| count.load(Ordering::Relaxed));
+        assert_eq!(counts, [1, record.plies.len(), 1]);
+        assert_eq!(record.tag("Blue"), Some("Counter"));
+        assert_eq!(record.tag("Green"), Some("average_bot"));
+    }
+
+    #[test]
+    fn test_engine_limits() {
+        // Every choice is made on the engine's own pool.
+        let threads = Arc::new(AtomicUsize::new(0));
+        let seen = threads.clone();
+        let mut bots = all_seats(choose_random);
+        bots[Player::First] = Box::new(move |_: &GameState, _: &[Action], _: &mut dyn RngCore| {
+            seen.fetch_max(rayon::current_num_threads(), Ordering::Relaxed);
+            0
+        });
+        let state = GameState::default();
+        let side = Player::First.side(&state.rules);
+        let engine = RolloutEngine::new(RolloutConfig::default().with_threads(3).unwrap());
+        let percent =
+            calculate_win_percentage_with(&engine, state.clone(), bots.clone(), side, 1).unwrap();
+        assert_eq!(threads.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            percent,
+            calculate_win_percentage(state.clone(), bots.clone(), side, 1)
+        );
+        let options = SampleOptions::default();
+        let (best, _) =
+            get_best_action_with(&engine, state.clone(), 6, bots.clone(), side, options, 1)
+                .unwrap();
+        assert!(state.get_actions(6).contains(&best));
+
+        // Stopped before the first game, there's nothing to estimate from.
+        let cancel = CancelToken::new();
+        let engine = RolloutEngine::new(RolloutConfig {
+            cancel: cancel.clone(),
+            ..Default::default()
+        });
+        cancel.cancel();
+        let bots = all_seats(choose_random);
+        let options = SampleOptions::default();
+        let ranked =
+            get_ranked_actions_with(&engine, state.clone(), 6, bots.clone(), side, options, 1);
+        assert!(ranked.is_empty());
+        assert!(
+            get_best_action_with(&engine, state.clone(), 6, bots.clone(), side, options, 1)
+                .is_none()
+        );
+        assert!(calculate_win_percentage_with(&engine, state, bots, side, 1).is_none());
+        assert_eq!(engine.metrics().games, 0);
+    }
 }
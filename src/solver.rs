@@ -0,0 +1,391 @@
+//! Exact win probabilities for positions close to the end.
+//!
+//! Enumerates every state reachable from a position, then solves the
+//! resulting Markov chain by iterating to a fixed point, which handles the
+//! cycles that captures and passes create. Moves are picked by the bots
+//! given, or by each player to maximise its own chance of ending up on the
+//! winning side. Positions with too many reachable states are sampled
+//! instead.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::board::{Action, Side};
+use crate::bots::{all_seats, average_bot, Bots};
+use crate::dice::{derive_seed, DiceSource, SeededDice};
+use crate::game_state::GameState;
+use crate::simulator::simulate_to_finish;
+use crate::Player;
+
+/// How the moves are chosen.
+#[derive(Clone)]
+pub enum Policy {
+    /// By these bots, which must pick the same move whenever they're shown
+    /// the same state and roll.
+    Bots(Bots),
+    /// By each player to maximise its own chance of being on the winning
+    /// side.
+    Optimal,
+}
+
+#[derive(Clone)]
+pub struct SolverConfig {
+    /// Reachable states above which the position is sampled instead.
+    pub max_states: usize,
+    /// Largest change in any value at which iterating stops.
+    pub tolerance: f64,
+    /// Games played when sampling.
+    pub fallback_games: u64,
+    /// Bots for sampling positions that are too big to solve for optimal
+    /// play.
+    pub fallback_bots: Bots,
+    pub seed: u64,
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        Self {
+            max_states: 200_000,
+            tolerance: 1e-12,
+            fallback_games: 1000,
+            fallback_bots: all_seats(average_bot),
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Solution {
+    /// Chance that the side asked about wins.
+    pub win_probability: f64,
+    /// Rolls until a side has won, passes included.
+    pub expected_length: f64,
+    /// States reachable from the position, or `None` if there were too many
+    /// and the answer was sampled.
+    pub states: Option<usize>,
+}
+
+/// Chance of ending up on the winning side, for each seat.
+pub(crate) type Rewards = [f64; 6];
+
+/// Identifies a state by what the rest of the game depends on.
+type Key = ([[u8; 4]; 6], Player, u8, Vec<Player>);
+
+fn key(state: &GameState) -> Key {
+    let mut pieces = [[0; 4]; 6];
+    for (player, data) in state.board().players {
+        pieces[player as usize] = data.pieces_positions;
+    }
+    (
+        pieces,
+        state.turn,
+        state.six_rolled(),
+        state.finishing_order().to_vec(),
+    )
+}
+
+/// Each move a roll allows, or passing, and the index of the state it leads
+/// to.
+pub(crate) type Successors = Vec<(Option<Action>, u32)>;
+
+/// States reachable from some positions, and where each roll can lead.
+pub(crate) struct Graph {
+    /// The states, starting with the positions explored from.
+    pub(crate) states: Vec<GameState>,
+    /// Winners of the states that are over.
+    winners: Vec<Option<Side>>,
+    /// What each roll can lead to.
+    pub(crate) successors: Vec<[Successors; 6]>,
+}
+
+impl Graph {
+    /// Explores from `starts`, giving up past `max_states`.
+    pub(crate) fn explore(
+        starts: &[GameState],
+        policy: &mut Policy,
+        max_states: usize,
+    ) -> Option<Self> {
+        let mut index = HashMap::new();
+        let mut states = vec![];
+        for start in starts {
+            index.entry(key(start)).or_insert_with(|| {
+                states.push(start.clone());
+                states.len() as u32 - 1
+            });
+        }
+        let mut graph = Graph {
+            states: vec![],
+            winners: vec![],
+            successors: vec![],
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut next = 0;
+        while next < states.len() {
+            let state = states[next].clone();
+            next += 1;
+            graph.winners.push(state.winner());
+            let mut successors: [Successors; 6] = Default::default();
+            if state.winner().is_none() {
+                for dice in 1..=6 {
+                    let actions = state
+                        .get_actions(dice)
+                        .into_iter()
+                        .sorted_by_key(|action| (action.player as usize, action.piece))
+                        .collect_vec();
+                    let children = if actions.is_empty() {
+                        let mut child = state.clone();
+                        child.roll(dice, |_, _| 0).expect("passing is legal");
+                        vec![(None, child)]
+                    } else {
+                        let chosen = match policy {
+                            Policy::Bots(bots) if actions.len() > 1 => {
+                                vec![bots[state.turn].choose(&state, dice, &actions, &mut rng)]
+                            }
+                            Policy::Bots(_) => vec![0],
+                            Policy::Optimal => (0..actions.len()).collect(),
+                        };
+                        chosen
+                            .into_iter()
+                            .map(|i| {
+                                let mut child = state.clone();
+                                child
+                                    .apply_action(dice, actions[i])
+                                    .expect("actions from get_actions are legal");
+                                (Some(actions[i]), child)
+                            })
+                            .collect()
+                    };
+                    for (action, child) in children {
+                        let len = index.len() as u32;
+                        let child_index = *index.entry(key(&child)).or_insert_with(|| {
+                            states.push(child);
+                            len
+                        });
+                        successors[dice as usize - 1].push((action, child_index));
+                    }
+                }
+            }
+            graph.successors.push(successors);
+            if states.len() > max_states {
+                return None;
+            }
+        }
+        graph.states = states;
+        Some(graph)
+    }
+
+    /// Rewards of every state, iterated until they stop changing by more
+    /// than `tolerance`, and which of the successors each player picks after
+    /// each roll.
+    pub(crate) fn solve(&self, tolerance: f64) -> (Vec<Rewards>, Vec<[usize; 6]>) {
+        let mut rewards = self
+            .winners
+            .iter()
+            .map(|winner| {
+                let mut rewards = [0.0; 6];
+                if let Some(winner) = winner {
+                    for player in enum_iterator::all::<Player>() {
+                        if winner.contains(player) {
+                            rewards[player as usize] = 1.0;
+                        }
+                    }
+                }
+                rewards
+            })
+            .collect_vec();
+        let pick = |rewards: &[Rewards], state: usize, roll: usize| {
+            let mover = self.states[state].turn as usize;
+            let children = &self.successors[state][roll];
+            let mut best = 0;
+            for (i, &(_, child)) in children.iter().enumerate() {
+                if rewards[child as usize][mover] > rewards[children[best].1 as usize][mover] {
+                    best = i;
+                }
+            }
+            best
+        };
+
+        // States found last are usually closest to the end, so sweeping
+        // backwards spreads the results faster.
+        for _ in 0..100_000 {
+            let mut change = 0.0f64;
+            for state in (0..rewards.len()).rev() {
+                if self.winners[state].is_some() {
+                    continue;
+                }
+                let mut value = [0.0; 6];
+                for roll in 0..6 {
+                    let child =
+                        self.successors[state][roll][pick(&rewards, state, roll)].1 as usize;
+                    for seat in 0..6 {
+                        value[seat] += rewards[child][seat] / 6.0;
+                    }
+                }
+                for seat in 0..6 {
+                    change = change.max((value[seat] - rewards[state][seat]).abs());
+                }
+                rewards[state] = value;
+            }
+            if change <= tolerance {
+                break;
+            }
+        }
+
+        let choices = (0..rewards.len())
+            .map(|state| {
+                let mut choice = [0; 6];
+                if self.winners[state].is_none() {
+                    for (roll, choice) in choice.iter_mut().enumerate() {
+                        *choice = pick(&rewards, state, roll);
+                    }
+                }
+                choice
+            })
+            .collect();
+        (rewards, choices)
+    }
+
+    /// Expected rolls until the game is won from each state, with the
+    /// players picking `choices`.
+    fn lengths(&self, choices: &[[usize; 6]], tolerance: f64) -> Vec<f64> {
+        let mut lengths = vec![0.0; choices.len()];
+        for _ in 0..100_000 {
+            let mut change = 0.0f64;
+            for state in (0..lengths.len()).rev() {
+                if self.winners[state].is_some() {
+                    continue;
+                }
+                let length = 1.0
+                    + (0..6)
+                        .map(|roll| {
+                            let child = self.successors[state][roll][choices[state][roll]].1;
+                            lengths[child as usize] / 6.0
+                        })
+                        .sum::<f64>();
+                change = change.max((length - lengths[state]).abs());
+                lengths[state] = length;
+            }
+            if change <= tolerance {
+                break;
+            }
+        }
+        lengths
+    }
+}
+
+/// Counts the rolls taken from a seeded source.
+struct CountedDice(SeededDice, u64);
+
+impl DiceSource for CountedDice {
+    fn roll(&mut self) -> Option<u8> {
+        self.1 += 1;
+        self.0.roll()
+    }
+
+    fn rng(&mut self) -> &mut dyn rand::RngCore {
+        self.0.rng()
+    }
+}
+
+pub struct Solver {
+    pub config: SolverConfig,
+}
+
+impl Solver {
+    pub fn new(config: SolverConfig) -> Self {
+        Self { config }
+    }
+
+    /// Chance that `side` wins from `state` and how long the game lasts, with
+    /// the moves chosen by `policy`.
+    pub fn solve(&self, state: &GameState, side: Side, mut policy: Policy) -> Solution {
+        let starts = [state.clone()];
+        let Some(graph) = Graph::explore(&starts, &mut policy, self.config.max_states) else {
+            let bots = match policy {
+                Policy::Bots(bots) => bots,
+                Policy::Optimal => self.config.fallback_bots.clone(),
+            };
+            return self.sample(state, side, bots);
+        };
+        let (rewards, choices) = graph.solve(self.config.tolerance);
+        let lengths = graph.lengths(&choices, self.config.tolerance);
+        let seat = state
+            .rules
+            .seats
+            .iter()
+            .find(|&&player| side.contains(player))
+            .map_or(0, |&player| player as usize);
+        Solution {
+            win_probability: rewards[0][seat],
+            expected_length: lengths[0],
+            states: Some(rewards.len()),
+        }
+    }
+
+    fn sample(&self, state: &GameState, side: Side, bots: Bots) -> Solution {
+        let games = self.config.fallback_games.max(1);
+        let (mut wins, mut rolls) = (0, 0);
+        for game in 0..games {
+            let mut dice = CountedDice(SeededDice::new(derive_seed(self.config.seed, game)), 0);
+            if simulate_to_finish(&mut state.clone(), &mut bots.clone(), &mut dice) == side {
+                wins += 1;
+            }
+            rolls += dice.1;
+        }
+        Solution {
+            win_probability: wins as f64 / games as f64,
+            expected_length: rolls as f64 / games as f64,
+            states: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bots::choose_closest_to_target;
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn test_endgame() {
+        let mut state =
+            GameState::with_rules(Default::default(), Player::First, RuleSet::two_player());
+        let last = state.rules.geometry.last_place();
+        for player in [Player::First, Player::Third] {
+            state.board_mut().players[player].pieces_positions = [last, last, last, last - 1];
+        }
+        // Whoever rolls a 1 first wins, and we roll first. We win in our
+        // first turn with chance w = 1/6 + 1/6 (1/6 + 1/36), counting the
+        // rolls again after a six, so we win with chance 1 / (2 - w).
+        let solver = Solver::new(SolverConfig::default());
+        let side = Player::First.side(&state.rules);
+        let solution = solver.solve(&state, side, Policy::Optimal);
+        assert!((solution.win_probability - 216.0 / 389.0).abs() < 1e-9);
+        assert!((solution.expected_length - 6.0).abs() < 1e-9);
+        // Each player to move, with no, one or two sixes rolled, and either
+        // having won.
+        assert_eq!(solution.states, Some(8));
+
+        state.board_mut().players[Player::First].pieces_positions =
+            [last, last, last - 5, last - 8];
+        state.board_mut().players[Player::Third].pieces_positions =
+            [last, last, last - 3, last - 7];
+        let policy = Policy::Bots(all_seats(choose_closest_to_target));
+        let optimal = solver.solve(&state, side, Policy::Optimal);
+        let greedy = solver.solve(&state, side, policy.clone());
+        assert!(optimal.states.is_some() && greedy.states.is_some());
+        assert!(optimal.win_probability >= greedy.win_probability - 1e-9);
+
+        let sampler = Solver::new(SolverConfig {
+            max_states: 10,
+            fallback_games: 200,
+            ..Default::default()
+        });
+        let sampled = sampler.solve(&state, side, policy);
+        assert_eq!(sampled.states, None);
+        assert!((sampled.win_probability - greedy.win_probability).abs() < 0.15);
+    }
+}
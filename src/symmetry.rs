@@ -0,0 +1,240 @@
+//! Canonical forms of positions that play out the same.
+//!
+//! The pieces of a seat can be swapped freely, and every seat's track is a
+//! rotation of every other's, so turning the board until the player to move
+//! sits in `Player::First`'s seat changes nothing either, as long as the
+//! seats taken and the safe spots look the same after turning it. The
+//! canonical form has each seat's pieces sorted by position and, whenever
+//! the rules allow it, `Player::First` to move. Caches, tablebases and
+//! evaluators can then share one entry between all the positions with the
+//! same canonical form, and map the actions they store back to the position
+//! they were asked about.
+
+use enum_map::{Enum, EnumMap};
+
+use crate::board::{Action, Board, Side};
+use crate::game_state::GameState;
+use crate::rules::RuleSet;
+use crate::Player;
+
+/// A position in canonical form, and how to get back to the one it came
+/// from.
+#[derive(Clone)]
+pub struct Canonical {
+    pub state: GameState,
+    /// Seats the board was turned by.
+    rotation: usize,
+    /// Piece of the original seat each canonical piece was, by canonical
+    /// seat.
+    pieces: EnumMap<Player, [u8; 4]>,
+}
+
+/// Whether turning the board by `rotation` seats leaves the same seats taken
+/// and the same spots safe.
+fn symmetric(rules: &RuleSet, rotation: usize) -> bool {
+    let geometry = rules.geometry;
+    let seats = geometry.seats();
+    rules
+        .seats
+        .iter()
+        .all(|&player| rules.is_seated(seats[(player as usize + rotation) % seats.len()]))
+        && rules
+            .safe_spots
+            .iter()
+            .all(|&spot| rules.is_safe(geometry.rotate(spot, rotation)))
+}
+
+impl Canonical {
+    pub fn new(state: &GameState) -> Self {
+        let rules = &state.rules;
+        let seats = rules.geometry.seats().len();
+        let rotation = match seats - state.turn as usize % seats {
+            rotation if symmetric(rules, rotation) => rotation % seats,
+            _ => 0,
+        };
+        let mut canonical = Self {
+            state: state.clone(),
+            rotation,
+            pieces: EnumMap::default(),
+        };
+
+        let mut board = Board::default();
+        for (player, data) in state.board().players {
+            if player as usize >= seats {
+                continue;
+            }
+            let seat = canonical.canonical_player(player);
+            let mut pieces = [0, 1, 2, 3];
+            pieces.sort_by_key(|&piece| data[piece]);
+            board.players[seat].pieces_positions = pieces.map(|piece| data[piece]);
+            canonical.pieces[seat] = pieces;
+        }
+        *canonical.state.board_mut() = board;
+        canonical.state.turn = canonical.canonical_player(state.turn);
+        canonical.state.finishing_order = state
+            .finishing_order()
+            .iter()
+            .map(|&player| canonical.canonical_player(player))
+            .collect();
+        canonical
+    }
+
+    /// Seats the board was turned by to get the player to move into
+    /// `Player::First`'s seat, or 0 if the rules don't allow turning it.
+    pub fn rotation(&self) -> usize {
+        self.rotation
+    }
+
+    /// The seat `player` of the original position sits in once canonical.
+    pub fn canonical_player(&self, player: Player) -> Player {
+        let seats = self.state.rules.geometry.seats().len();
+        Player::from_usize((player as usize + self.rotation) % seats)
+    }
+
+    /// The seat of the original position that `player` of the canonical one
+    /// sat in.
+    pub fn original_player(&self, player: Player) -> Player {
+        let seats = self.state.rules.geometry.seats().len();
+        Player::from_usize((player as usize + seats - self.rotation) % seats)
+    }
+
+    fn map_side(side: Side, map: impl Fn(Player) -> Player) -> Side {
+        match side {
+            Side::Player(player) => Side::Player(map(player)),
+            // Teams alternate seats, so a team's first player finds the
+            // team it turns into.
+            Side::Team(team) => Side::Team(map(team.get_players()[0]).team()),
+        }
+    }
+
+    pub fn canonical_side(&self, side: Side) -> Side {
+        Self::map_side(side, |player| self.canonical_player(player))
+    }
+
+    pub fn original_side(&self, side: Side) -> Side {
+        Self::map_side(side, |player| self.original_player(player))
+    }
+
+    /// The action of the original position that `action` of the canonical
+    /// one stands for.
+    pub fn original_action(&self, action: Action) -> Action {
+        Action {
+            player: self.original_player(action.player),
+            piece: self.pieces[action.player][action.piece as usize & 3],
+            ..action
+        }
+    }
+
+    /// The action of the canonical position that `action` of the original
+    /// one turns into.
+    pub fn canonical_action(&self, action: Action) -> Action {
+        let player = self.canonical_player(action.player);
+        let piece = self.pieces[player]
+            .iter()
+            .position(|&piece| piece == action.piece)
+            .unwrap_or(action.piece as usize);
+        Action {
+            player,
+            piece: piece as u8,
+            ..action
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Team;
+    use crate::solver::{Policy, Solver, SolverConfig};
+
+    #[test]
+    fn test_canonical() {
+        let rules = RuleSet::two_player();
+        let mut state = GameState::with_rules(Board::default(), Player::Third, rules);
+        let last = state.rules.geometry.last_place();
+        state.board_mut().players[Player::First].pieces_positions = [last, 20, last, last - 2];
+        state.board_mut().players[Player::Third].pieces_positions = [last - 1, last, 30, last];
+        let canonical = Canonical::new(&state);
+        assert_eq!(canonical.rotation(), 2);
+        assert_eq!(canonical.state.turn, Player::First);
+        assert_eq!(
+            canonical.state.board().players[Player::First].pieces_positions,
+            [30, last - 1, last, last]
+        );
+        assert_eq!(
+            canonical.state.board().players[Player::Third].pieces_positions,
+            [20, last - 2, last, last]
+        );
+
+        // Every action maps back to one of the original position's.
+        for dice in 1..=6 {
+            let original = state.get_actions(dice);
+            let canonical_actions = canonical.state.get_actions(dice);
+            assert_eq!(original.len(), canonical_actions.len());
+            for &action in &canonical_actions {
+                let back = canonical.original_action(action);
+                assert!(original
+                    .iter()
+                    .any(|a| a.piece == back.piece && a == &back && a.to == back.to));
+                assert_eq!(canonical.canonical_action(back).piece, action.piece);
+            }
+        }
+
+        // Both play out the same for the side to move, and share a key with
+        // any other arrangement of the same pieces.
+        let solver = Solver::new(SolverConfig::default());
+        let side = Player::Third.side(&state.rules);
+        let original = solver.solve(&state, side, Policy::Optimal);
+        let turned = solver.solve(
+            &canonical.state,
+            canonical.canonical_side(side),
+            Policy::Optimal,
+        );
+        assert!((original.win_probability - turned.win_probability).abs() < 1e-9);
+        let mut swapped = state.clone();
+        swapped.board_mut().players[Player::First].pieces_positions = [last - 2, last, 20, last];
+        assert_eq!(Canonical::new(&swapped).state.key(), canonical.state.key());
+
+        // Without the fourth seat, turning the board changes the game.
+        let rules = RuleSet::three_player();
+        let state = GameState::with_rules(Board::default(), Player::Second, rules);
+        let canonical = Canonical::new(&state);
+        assert_eq!(canonical.rotation(), 0);
+        assert_eq!(canonical.state.turn, Player::Second);
+        assert_eq!(
+            canonical.original_side(Side::Player(Player::Second)),
+            Side::Player(Player::Second)
+        );
+    }
+
+    #[test]
+    fn test_canonical_teams() {
+        let mut state = GameState::new(Board::default(), Player::Second);
+        let last = state.rules.geometry.last_place();
+        // One piece each left in the home stretch, out of reach of the
+        // others.
+        for (player, left) in [
+            (Player::First, 1),
+            (Player::Second, 3),
+            (Player::Third, 2),
+            (Player::Fourth, 4),
+        ] {
+            state.board_mut().players[player].pieces_positions = [last, last, last, last - left];
+        }
+        let solver = Solver::new(SolverConfig::default());
+        for (turn, rotation, team, turned_team) in [
+            (Player::Second, 3, Team::Even, Team::Odd),
+            (Player::Third, 2, Team::Odd, Team::Odd),
+            (Player::Fourth, 1, Team::Odd, Team::Even),
+        ] {
+            state.turn = turn;
+            let canonical = Canonical::new(&state);
+            assert_eq!(canonical.rotation(), rotation);
+            assert_eq!(canonical.canonical_side(team.into()), turned_team.into());
+            assert_eq!(canonical.original_side(turned_team.into()), team.into());
+            let original = solver.solve(&state, team.into(), Policy::Optimal);
+            let turned = solver.solve(&canonical.state, turned_team.into(), Policy::Optimal);
+            assert!((original.win_probability - turned.win_probability).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! Endgame tablebases.
+//!
+//! A tablebase holds, for every position where each seat has at most a few
+//! pieces left to bring home, the chance that the side to move wins with
+//! perfect play and the best move for each roll. Positions are stored in a
+//! fixed order, so where one is in the file is worked out from the position
+//! itself, and the file needs no lookup table.
+//!
+//! The file is the magic bytes, the length of the rules as JSON and the
+//! rules, the pieces per seat and the number of entries, and then every
+//! entry: the win chance as an `f32` and one byte per roll for the move.
+//! Numbers are little-endian.
+//!
+//! Tablebases are limited to `MAX_ENTRIES` entries, which rules out the
+//! four-seat rules and more than one piece per seat for two players.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use rand::RngCore;
+
+use crate::board::{Action, Board, PiecePosition, Side};
+use crate::bots::Bot;
+use crate::game_state::GameState;
+use crate::rules::RuleSet;
+use crate::solver::{Graph, Policy};
+use crate::Player;
+
+const MAGIC: &[u8; 8] = b"LUDOTB01";
+/// Move byte for rolls that don't allow any.
+const NO_ACTION: u8 = u8::MAX;
+
+/// Most entries a tablebase may have. Generating one takes about a
+/// kilobyte of memory per entry: the two-player rules with one piece per
+/// seat need 9,600 entries and the three-player ones 576,000, which take a
+/// few seconds and about 600 MB, while two pieces per seat for two
+/// players would need about 4 million and the four-seat rules about 31
+/// million with a single piece.
+pub const MAX_ENTRIES: u64 = 1 << 20;
+
+/// Longest rules a tablebase file may hold, in bytes of JSON, so that a
+/// corrupt length doesn't get allocated.
+const MAX_RULES_LEN: u32 = 1 << 16;
+
+#[derive(Debug)]
+pub enum TablebaseError {
+    Io(io::Error),
+    /// The file doesn't start like a tablebase.
+    BadMagic,
+    BadRules(serde_json::Error),
+    /// The rules are said to be longer than `MAX_RULES_LEN` bytes.
+    RulesTooLong(u32),
+    /// Pieces per seat other than 1 to 4.
+    BadPieces(u8),
+    /// The file doesn't have as many entries as its rules call for.
+    WrongSize {
+        expected: u64,
+        found: u64,
+    },
+    /// The rules and pieces per seat call for more than `MAX_ENTRIES`
+    /// entries.
+    TooLarge,
+}
+
+impl Display for TablebaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TablebaseError::Io(err) => write!(f, "couldn't access tablebase: {}", err),
+            TablebaseError::BadMagic => write!(f, "not a tablebase file"),
+            TablebaseError::BadRules(err) => write!(f, "malformed tablebase rules: {}", err),
+            TablebaseError::RulesTooLong(len) => {
+                write!(f, "tablebase rules are {} bytes long", len)
+            }
+            TablebaseError::BadPieces(pieces) => {
+                write!(
+                    f,
+                    "tablebase has {} pieces per seat, expected 1 to 4",
+                    pieces
+                )
+            }
+            TablebaseError::WrongSize { expected, found } => {
+                write!(f, "tablebase has {} entries, expected {}", found, expected)
+            }
+            TablebaseError::TooLarge => {
+                write!(f, "tablebase would have more than {} entries", MAX_ENTRIES)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TablebaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TablebaseError::Io(err) => Some(err),
+            TablebaseError::BadRules(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TablebaseError {
+    fn from(err: io::Error) -> Self {
+        TablebaseError::Io(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Entry {
+    /// Chance that the side to move wins, or NaN for positions that can't
+    /// come up.
+    win: f32,
+    /// Best move for each roll, as the seat's place in the rules times the
+    /// pieces per seat plus the piece's place among the seat's unfinished
+    /// pieces.
+    actions: [u8; 6],
+}
+
+const MISSING: Entry = Entry {
+    win: f32::NAN,
+    actions: [NO_ACTION; 6],
+};
+
+fn binomial(n: u64, k: u64) -> u64 {
+    if k > n {
+        return 0;
+    }
+    (0..k).fold(1, |result, i| result * (n - i) / (i + 1))
+}
+
+fn check_pieces(pieces: u8) -> Result<u8, TablebaseError> {
+    if (1..=4).contains(&pieces) {
+        Ok(pieces)
+    } else {
+        Err(TablebaseError::BadPieces(pieces))
+    }
+}
+
+pub struct Tablebase {
+    rules: RuleSet,
+    pieces: u8,
+    entries: Vec<Entry>,
+}
+
+impl Tablebase {
+    /// Solves every position under `rules` where no seat has more than
+    /// `pieces` pieces that haven't finished, from 1 to 4, unless there are
+    /// more than `MAX_ENTRIES` of them.
+    ///
+    /// Tables for rules with more than two sides, such as three players
+    /// without teams, can be probed and played by `TablebaseBot`, but
+    /// `simulator::get_best_action` doesn't use them: they only hold the
+    /// chances of the player to move, which don't give the other sides'.
+    pub fn generate(rules: RuleSet, pieces: u8) -> Result<Self, TablebaseError> {
+        let mut tablebase = Self {
+            rules,
+            pieces: check_pieces(pieces)?,
+            entries: vec![],
+        };
+        let size = tablebase.size().ok_or(TablebaseError::TooLarge)?;
+        let starts = (0..size)
+            .filter_map(|index| tablebase.state_at(index))
+            .collect_vec();
+        let graph = Graph::explore(&starts, &mut Policy::Optimal, usize::MAX)
+            .expect("there's no limit on the states");
+        let (rewards, choices) = graph.solve(1e-12);
+
+        tablebase.entries = vec![MISSING; size as usize];
+        for (i, state) in graph.states.iter().enumerate() {
+            let index = tablebase
+                .index(state)
+                .expect("the positions covered only lead to each other");
+            let mut actions = [NO_ACTION; 6];
+            if state.winner().is_none() {
+                for (roll, action) in actions.iter_mut().enumerate() {
+                    let (best, _) = graph.successors[i][roll][choices[i][roll]];
+                    *action = best.map_or(NO_ACTION, |best| tablebase.encode(state, best));
+                }
+            }
+            tablebase.entries[index] = Entry {
+                win: rewards[i][state.turn as usize] as f32,
+                actions,
+            };
+        }
+        Ok(tablebase)
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    /// Most pieces a seat can have left in the positions covered.
+    pub fn pieces(&self) -> u8 {
+        self.pieces
+    }
+
+    /// Ways to place one seat's unfinished pieces, counting pieces in the
+    /// same place once.
+    fn placements(&self) -> u64 {
+        let values = self.rules.geometry.last_place() as u64 + 1;
+        binomial(values + self.pieces as u64 - 1, self.pieces as u64)
+    }
+
+    fn sixes(&self) -> u64 {
+        self.rules.max_consecutive_sixes.max(1) as u64
+    }
+
+    /// Entries needed for every position, if no more than `MAX_ENTRIES`.
+    fn size(&self) -> Option<u64> {
+        let seats = self.rules.seats.len() as u64;
+        self.placements()
+            .checked_pow(seats as u32)?
+            .checked_mul(seats * self.sixes())
+            .filter(|&size| size <= MAX_ENTRIES)
+    }
+
+    /// Where the pieces of `player` are, sorted and padded with finished
+    /// ones to the pieces per seat, if there aren't too many left.
+    fn slots(&self, board: &Board, player: Player) -> Option<Vec<PiecePosition>> {
+        let last = self.rules.geometry.last_place();
+        let mut slots = board.players[player]
+            .pieces_positions
+            .into_iter()
+            .filter(|&pos| pos != last)
+            .sorted()
+            .collect_vec();
+        if slots.len() > self.pieces as usize {
+            return None;
+        }
+        slots.resize(self.pieces as usize, last);
+        Some(slots)
+    }
+
+    /// Where `state` is stored, if it's covered.
+    fn index(&self, state: &GameState) -> Option<usize> {
+        if *state.rules != self.rules {
+            return None;
+        }
+        let seats = &self.rules.seats;
+        let mut index = 0;
+        for &player in seats {
+            // Ranks the sorted slots in the combinatorial number system.
+            let rank = self
+                .slots(state.board(), player)?
+                .iter()
+                .enumerate()
+                .map(|(i, &pos)| binomial(pos as u64 + i as u64, i as u64 + 1))
+                .sum::<u64>();
+            index = index * self.placements() + rank;
+        }
+        let turn = seats.iter().position(|&player| player == state.turn)? as u64;
+        index = index * seats.len() as u64 + turn;
+        Some((index * self.sixes() + state.six_rolled() as u64) as usize)
+    }
+
+    /// The position stored at `index`, if it can come up in a game.
+    fn state_at(&self, index: u64) -> Option<GameState> {
+        let seats = &self.rules.seats;
+        let six_rolled = (index % self.sixes()) as u8;
+        let mut index = index / self.sixes();
+        let turn = seats[(index % seats.len() as u64) as usize];
+        index /= seats.len() as u64;
+
+        let last = self.rules.geometry.last_place();
+        let mut board = Board::default();
+        for &player in seats.iter().rev() {
+            let mut rank = index % self.placements();
+            index /= self.placements();
+            let mut pieces = [last; 4];
+            for i in (0..self.pieces as u64).rev() {
+                let mut c = i;
+                while binomial(c + 1, i + 1) <= rank {
+                    c += 1;
+                }
+                rank -= binomial(c, i + 1);
+                pieces[i as usize] = (c - i) as PiecePosition;
+            }
+            board.players[player].pieces_positions = pieces;
+        }
+
+        let state = GameState::from_parts(board, turn, self.rules.clone(), six_rolled, None);
+        let waiting = state.winner().is_none() && state.board().has_finished(turn, &self.rules);
+        (state.validate().is_ok() && !waiting).then_some(state)
+    }
+
+    fn encode(&self, state: &GameState, action: Action) -> u8 {
+        let seat = self
+            .rules
+            .seats
+            .iter()
+            .position(|&player| player == action.player)
+            .expect("only seated players move");
+        let slot = self
+            .slots(state.board(), action.player)
+            .and_then(|slots| slots.iter().position(|&pos| pos == action.from))
+            .expect("the piece moved is one of the seat's unfinished ones");
+        (seat * self.pieces as usize + slot) as u8
+    }
+
+    /// Chance that the side of the player to move wins from `state` with
+    /// perfect play, if the tablebase covers it.
+    pub fn probe(&self, state: &GameState) -> Option<f64> {
+        let entry = self.entries.get(self.index(state)?)?;
+        (!entry.win.is_nan()).then_some(entry.win as f64)
+    }
+
+    /// Best move after rolling `dice` in `state`, if the tablebase covers
+    /// it and there are any moves.
+    pub fn best_action(&self, state: &GameState, dice: u8) -> Option<Action> {
+        let entry = self.entries.get(self.index(state)?)?;
+        let code = *entry.actions.get(dice.checked_sub(1)? as usize)?;
+        if entry.win.is_nan() || code == NO_ACTION {
+            return None;
+        }
+        let player = self.rules.seats[code as usize / self.pieces as usize];
+        let from = self.slots(state.board(), player)?[code as usize % self.pieces as usize];
+        state
+            .get_actions(dice)
+            .into_iter()
+            .find(|action| action.player == player && action.from == from)
+    }
+
+    /// Best move after rolling `dice` in `state`, and the chance that `side`
+    /// wins after it. Only answers when there are two sides, since the
+    /// tablebase only knows the chances of the side to move.
+    pub fn best_action_value(
+        &self,
+        state: &GameState,
+        dice: u8,
+        side: Side,
+    ) -> Option<(Action, f64)> {
+        let sides = self
+            .rules
+            .seats
+            .iter()
+            .map(|p| p.side(&self.rules))
+            .unique();
+        if sides.count() != 2 {
+            return None;
+        }
+        let action = self.best_action(state, dice)?;
+        let mut after = state.clone();
+        after.apply_action(dice, action).ok()?;
+        let win = self.probe(&after)?;
+        Some((
+            action,
+            if side.contains(after.turn) {
+                win
+            } else {
+                1.0 - win
+            },
+        ))
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let rules = serde_json::to_vec(&self.rules)?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(rules.len() as u32).to_le_bytes())?;
+        writer.write_all(&rules)?;
+        writer.write_all(&[self.pieces])?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for entry in &self.entries {
+            writer.write_all(&entry.win.to_le_bytes())?;
+            writer.write_all(&entry.actions)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(mut reader: impl Read) -> Result<Self, TablebaseError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(TablebaseError::BadMagic);
+        }
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len);
+        if len > MAX_RULES_LEN {
+            return Err(TablebaseError::RulesTooLong(len));
+        }
+        let mut rules = vec![0; len as usize];
+        reader.read_exact(&mut rules)?;
+        let rules = serde_json::from_slice(&rules).map_err(TablebaseError::BadRules)?;
+        let mut pieces = [0];
+        reader.read_exact(&mut pieces)?;
+        let mut tablebase = Self {
+            rules,
+            pieces: check_pieces(pieces[0])?,
+            entries: vec![],
+        };
+
+        let mut count = [0; 8];
+        reader.read_exact(&mut count)?;
+        let found = u64::from_le_bytes(count);
+        let expected = tablebase.size().ok_or(TablebaseError::TooLarge)?;
+        if found != expected {
+            return Err(TablebaseError::WrongSize { expected, found });
+        }
+        let mut entry = [0; 10];
+        for _ in 0..found {
+            reader.read_exact(&mut entry)?;
+            tablebase.entries.push(Entry {
+                win: f32::from_le_bytes(entry[..4].try_into().unwrap()),
+                actions: entry[4..].try_into().unwrap(),
+            });
+        }
+        Ok(tablebase)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TablebaseError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TablebaseError> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Plays the tablebase's moves where it has them, and asks `fallback`
+/// everywhere else.
+#[derive(Clone)]
+pub struct TablebaseBot {
+    pub tablebase: Arc<Tablebase>,
+    pub fallback: Box<dyn Bot>,
+}
+
+impl TablebaseBot {
+    pub fn new(tablebase: Arc<Tablebase>, fallback: impl Bot + 'static) -> Self {
+        Self {
+            tablebase,
+            fallback: Box::new(fallback),
+        }
+    }
+}
+
+impl Bot for TablebaseBot {
+    fn choose(
+        &mut self,
+        state: &GameState,
+        dice: u8,
+        actions: &[Action],
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        self.tablebase
+            .best_action(state, dice)
+            .and_then(|best| actions.iter().position(|action| action == &best))
+            .unwrap_or_else(|| self.fallback.choose(state, dice, actions, rng))
+    }
+
+    fn new_game(&mut self, state: &GameState) {
+        self.fallback.new_game(state);
+    }
+
+    fn observe_move(&mut self, ply: &crate::history::Ply, state: &GameState) {
+        self.fallback.observe_move(ply, state);
+    }
+
+    fn game_over(&mut self, state: &GameState) {
+        self.fallback.game_over(state);
+    }
+
+    fn box_clone(&self) -> Box<dyn Bot> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bots::{all_seats, average_bot};
+    use crate::simulator::get_best_action;
+
+    #[test]
+    fn test_tablebase() {
+        let rules = RuleSet::two_player();
+        let tablebase = Tablebase::generate(rules.clone(), 1).unwrap();
+        let mut bytes = vec![];
+        tablebase.write_to(&mut bytes).unwrap();
+        let tablebase = Arc::new(Tablebase::read_from(bytes.as_slice()).unwrap());
+        assert_eq!(tablebase.entries.len(), 40 * 40 * 2 * 3);
+
+        // The same position as the solver's: whoever rolls a 1 first wins.
+        let mut state = GameState::with_rules(Default::default(), Player::First, rules);
+        let last = state.rules.geometry.last_place();
+        for player in [Player::First, Player::Third] {
+            state.board_mut().players[player].pieces_positions = [last, last, last, last - 1];
+        }
+        assert!((tablebase.probe(&state).unwrap() - 216.0 / 389.0).abs() < 1e-6);
+        assert_eq!(tablebase.best_action(&state, 2), None);
+
+        state.board_mut().players[Player::First].pieces_positions = [0, last, last, last];
+        let best = tablebase.best_action(&state, 6).unwrap();
+        assert_eq!((best.player, best.piece, best.to), (Player::First, 0, 1));
+        let mut bot = TablebaseBot::new(tablebase.clone(), average_bot);
+        let actions = state.get_actions(6).into_iter().collect_vec();
+        let choice = bot.choose(&state, 6, &actions, &mut rand::thread_rng());
+        assert_eq!(actions[choice], best);
+
+        let side = Player::First.side(&state.rules);
+        let bots = all_seats(average_bot);
+        let (action, win) =
+            get_best_action(state.clone(), 6, bots.clone(), side, 0, Some(&tablebase)).unwrap();
+        assert_eq!(action, best);
+        assert_eq!(
+            Some(win),
+            tablebase
+                .best_action_value(&state, 6, side)
+                .map(|(_, win)| win)
+        );
+        // Without it, the action is rolled out.
+        let (_, rolled) = get_best_action(state.clone(), 6, bots, side, 0, None).unwrap();
+        assert_ne!(rolled, win);
+
+        state.board_mut().players[Player::First].pieces_positions = [0, 0, last, last];
+        assert_eq!(tablebase.probe(&state), None);
+
+        // Corrupt files are turned down before anything is allocated.
+        let mut corrupt = bytes.clone();
+        corrupt[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Tablebase::read_from(corrupt.as_slice()),
+            Err(TablebaseError::RulesTooLong(u32::MAX))
+        ));
+        let mut corrupt = bytes.clone();
+        corrupt[12 + u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize] = 9;
+        assert!(matches!(
+            Tablebase::read_from(corrupt.as_slice()),
+            Err(TablebaseError::BadPieces(9))
+        ));
+
+        // Turned down before anything is built, overflowing or not.
+        for (rules, pieces) in [(RuleSet::two_player(), 2), (RuleSet::classic(), 4)] {
+            assert!(matches!(
+                Tablebase::generate(rules, pieces),
+                Err(TablebaseError::TooLarge)
+            ));
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! Tournaments between registered bots.
+//!
+//! Every match is between two bots, one playing the side of a seat and the
+//! other every seat against it. The games of a match cycle through the seats,
+//! so both bots move first equally often and play both teams. The games of
+//! each round are played in parallel, and the standings rate the bots on the
+//! Elo scale, fitted to all the games at once.
+
+use itertools::Itertools;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bots::{Bot, Bots};
+use crate::dice::{derive_seed, SeededDice};
+use crate::game_state::GameState;
+use crate::simulator::simulate_to_finish;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pairing {
+    /// Every bot plays every other once.
+    RoundRobin,
+    /// Each round pairs bots with similar scores that haven't met yet.
+    Swiss { rounds: u32 },
+}
+
+#[derive(Clone)]
+pub struct TournamentConfig {
+    /// Position every game starts from, which also sets the rules.
+    pub start: GameState,
+    pub pairing: Pairing,
+    /// Games per match, best a multiple of the number of seats so that the
+    /// bots get the same turns.
+    pub games_per_match: u32,
+    pub seed: u64,
+}
+
+impl Default for TournamentConfig {
+    fn default() -> Self {
+        Self {
+            start: GameState::default(),
+            pairing: Pairing::RoundRobin,
+            games_per_match: 40,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    pub name: String,
+    pub elo: f64,
+    /// 95% confidence interval around `elo`.
+    pub low: f64,
+    pub high: f64,
+    pub wins: u32,
+    pub games: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Standings {
+    /// Best rated first.
+    pub ratings: Vec<Rating>,
+    /// Names of the bots in the order they were registered, which the
+    /// matrices follow.
+    pub names: Vec<String>,
+    /// `wins[i][j]` is how many games bot `i` won against bot `j`.
+    pub wins: Vec<Vec<u32>>,
+    pub games: Vec<Vec<u32>>,
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Standings {
+    fn new(names: Vec<String>, wins: Vec<Vec<u32>>, games: Vec<Vec<u32>>) -> Self {
+        let elo = fit_elo(&wins, &games);
+        let ratings = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Rating {
+                name: name.clone(),
+                elo: elo[i].0,
+                low: elo[i].0 - 1.96 * elo[i].1,
+                high: elo[i].0 + 1.96 * elo[i].1,
+                wins: wins[i].iter().sum(),
+                games: games[i].iter().sum(),
+            })
+            .sorted_by(|a, b| b.elo.total_cmp(&a.elo))
+            .collect();
+        Self {
+            ratings,
+            names,
+            wins,
+            games,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("standings serialize")
+    }
+
+    /// The rating table, one bot per line.
+    pub fn to_csv(&self) -> String {
+        let mut csv = "name,elo,low,high,wins,games\n".to_string();
+        for rating in &self.ratings {
+            csv += &format!(
+                "{},{:.1},{:.1},{:.1},{},{}\n",
+                csv_field(&rating.name),
+                rating.elo,
+                rating.low,
+                rating.high,
+                rating.wins,
+                rating.games
+            );
+        }
+        csv
+    }
+
+    /// Win rate of each bot, by row, against each other bot, by column,
+    /// left empty for bots that didn't meet.
+    pub fn head_to_head_csv(&self) -> String {
+        let mut csv = std::iter::once(String::new())
+            .chain(self.names.iter().map(|name| csv_field(name)))
+            .join(",");
+        csv.push('\n');
+        for (i, name) in self.names.iter().enumerate() {
+            csv += &std::iter::once(csv_field(name))
+                .chain((0..self.names.len()).map(|j| match self.games[i][j] {
+                    0 => String::new(),
+                    games => format!("{:.3}", self.wins[i][j] as f64 / games as f64),
+                }))
+                .join(",");
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+/// Elo rating and its standard error for each bot, from the Bradley-Terry
+/// model fitted by maximum likelihood, with the ratings averaging zero.
+///
+/// Every pair that met gets an extra drawn game, so that bots that won or
+/// lost everything still get finite ratings.
+fn fit_elo(wins: &[Vec<u32>], games: &[Vec<u32>]) -> Vec<(f64, f64)> {
+    let n = wins.len();
+    let won = |i: usize, j: usize| match games[i][j] {
+        0 => 0.0,
+        _ => wins[i][j] as f64 + 0.5,
+    };
+    let played = |i: usize, j: usize| match games[i][j] {
+        0 => 0.0,
+        games => games as f64 + 1.0,
+    };
+
+    // Minorization-maximization on the strengths 10^(elo / 400).
+    let mut strength = vec![1.0; n];
+    for _ in 0..1000 {
+        let next = (0..n)
+            .map(|i| {
+                let total_won = (0..n).map(|j| won(i, j)).sum::<f64>();
+                let denominator = (0..n)
+                    .map(|j| played(i, j) / (strength[i] + strength[j]))
+                    .sum::<f64>();
+                if denominator == 0.0 {
+                    1.0
+                } else {
+                    total_won / denominator
+                }
+            })
+            .collect_vec();
+        let mean_log = next.iter().map(|s: &f64| s.ln()).sum::<f64>() / n.max(1) as f64;
+        let next = next.iter().map(|s| s / mean_log.exp()).collect_vec();
+        let change = next
+            .iter()
+            .zip(&strength)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        strength = next;
+        if change < 1e-10 {
+            break;
+        }
+    }
+
+    let scale = 400.0 / std::f64::consts::LN_10;
+    (0..n)
+        .map(|i| {
+            // Observed information of the bot's own rating, holding the
+            // others fixed.
+            let information = (0..n)
+                .map(|j| {
+                    let p = strength[i] / (strength[i] + strength[j]);
+                    played(i, j) * p * (1.0 - p)
+                })
+                .sum::<f64>();
+            let error = if information > 0.0 {
+                scale / information.sqrt()
+            } else {
+                f64::INFINITY
+            };
+            (scale * strength[i].ln(), error)
+        })
+        .collect()
+}
+
+pub struct Tournament {
+    pub config: TournamentConfig,
+    entrants: Vec<(String, Box<dyn Bot>)>,
+}
+
+impl Tournament {
+    pub fn new(config: TournamentConfig) -> Self {
+        Self {
+            config,
+            entrants: vec![],
+        }
+    }
+
+    /// Adds a bot under its own name.
+    pub fn register(&mut self, bot: impl Bot + 'static) -> &mut Self {
+        let name = bot.name();
+        self.register_as(name, bot)
+    }
+
+    /// Adds a bot under `name`, for entering the same bot with different
+    /// settings.
+    pub fn register_as(&mut self, name: impl Into<String>, bot: impl Bot + 'static) -> &mut Self {
+        self.entrants.push((name.into(), Box::new(bot)));
+        self
+    }
+
+    /// Plays game `game` of a match, returning whether `first` won.
+    fn play(&self, first: usize, second: usize, game: u32, seed: u64) -> bool {
+        let start = &self.config.start;
+        let seats = &start.rules.seats;
+        let side = seats[game as usize % seats.len()].side(&start.rules);
+        let mut bots = Bots::seated(&start.rules, |player| {
+            if side.contains(player) {
+                self.entrants[first].1.box_clone()
+            } else {
+                self.entrants[second].1.box_clone()
+            }
+        });
+        let mut dice = SeededDice::new(seed);
+        simulate_to_finish(&mut start.clone(), &mut bots, &mut dice) == side
+    }
+
+    /// Pairs for the next Swiss round: the bots are ranked by win rate and
+    /// each takes the best ranked one left that it hasn't met, if any. With
+    /// an odd number of bots, the last one sits out.
+    fn swiss_round(&self, wins: &[Vec<u32>], games: &[Vec<u32>]) -> Vec<(usize, usize)> {
+        let score = |i: usize| {
+            let games = games[i].iter().sum::<u32>();
+            wins[i].iter().sum::<u32>() as f64 / games.max(1) as f64
+        };
+        let mut unpaired = (0..self.entrants.len())
+            .sorted_by(|&a, &b| score(b).total_cmp(&score(a)))
+            .collect_vec();
+        let mut pairs = vec![];
+        while unpaired.len() >= 2 {
+            let first = unpaired.remove(0);
+            let opponent = unpaired
+                .iter()
+                .position(|&other| games[first][other] == 0)
+                .unwrap_or(0);
+            pairs.push((first, unpaired.remove(opponent)));
+        }
+        pairs
+    }
+
+    pub fn run(&self) -> Standings {
+        let n = self.entrants.len();
+        let mut wins = vec![vec![0; n]; n];
+        let mut games = vec![vec![0; n]; n];
+        let rounds = match self.config.pairing {
+            Pairing::RoundRobin => 1,
+            Pairing::Swiss { rounds } => rounds,
+        };
+        let mut matches_played = 0u64;
+        for _ in 0..rounds {
+            let pairs = match self.config.pairing {
+                Pairing::RoundRobin => (0..n).tuple_combinations().collect_vec(),
+                Pairing::Swiss { .. } => self.swiss_round(&wins, &games),
+            };
+            let per_match = self.config.games_per_match;
+            let results = (0..pairs.len() as u64 * per_match as u64)
+                .into_par_iter()
+                .map(|index| {
+                    let (first, second) = pairs[(index / per_match as u64) as usize];
+                    let game = (index % per_match as u64) as u32;
+                    let seed =
+                        derive_seed(self.config.seed, matches_played * per_match as u64 + index);
+                    // Swap who plays the seat every other lap of the seats.
+                    let laps = game as usize / self.config.start.rules.seats.len();
+                    if laps.is_multiple_of(2) {
+                        (first, second, self.play(first, second, game, seed))
+                    } else {
+                        (second, first, self.play(second, first, game, seed))
+                    }
+                })
+                .collect::<Vec<_>>();
+            for (first, second, first_won) in results {
+                let (winner, loser) = if first_won {
+                    (first, second)
+                } else {
+                    (second, first)
+                };
+                wins[winner][loser] += 1;
+                games[winner][loser] += 1;
+                games[loser][winner] += 1;
+            }
+            matches_played += pairs.len() as u64;
+        }
+        let names = self.entrants.iter().map(|(name, _)| name.clone()).collect();
+        Standings::new(names, wins, games)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bots::{average_bot, choose_closest_to_target, choose_random};
+    use crate::rules::RuleSet;
+    use crate::Player;
+
+    #[test]
+    fn test_tournament() {
+        let config = TournamentConfig {
+            start: GameState::with_rules(Default::default(), Player::First, RuleSet::two_player()),
+            games_per_match: 20,
+            ..Default::default()
+        };
+        let mut tournament = Tournament::new(config);
+        tournament
+            .register(choose_random)
+            .register(average_bot)
+            .register(choose_closest_to_target);
+        let standings = tournament.run();
+        assert_eq!(standings.names[1], "average_bot");
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_eq!(standings.games[i][j], if i == j { 0 } else { 20 });
+                assert_eq!(
+                    standings.wins[i][j] + standings.wins[j][i],
+                    standings.games[i][j]
+                );
+            }
+        }
+        assert_eq!(standings.ratings[0].name, "average_bot");
+        let elo = standings
+            .ratings
+            .iter()
+            .map(|rating| rating.elo)
+            .sum::<f64>();
+        assert!(elo.abs() < 1e-6);
+        assert!(standings
+            .ratings
+            .iter()
+            .all(|r| r.low < r.elo && r.elo < r.high));
+        assert_eq!(standings.to_csv().lines().count(), 4);
+        assert!(standings.head_to_head_csv().starts_with(",choose_random,"));
+
+        tournament.config.pairing = Pairing::Swiss { rounds: 2 };
+        let standings = tournament.run();
+        let matches = standings
+            .games
+            .iter()
+            .flatten()
+            .filter(|&&games| games > 0)
+            .count();
+        assert_eq!(matches, 4);
+    }
+}
@@ -0,0 +1,250 @@
+//! A bounded transposition table that threads can share.
+//!
+//! Each key has a single slot, picked by its low bits, so the table never
+//! grows past its capacity and a new entry for a taken slot either replaces
+//! the one there or is dropped, as the replacement policy says. Slots are
+//! split between locks, so threads mostly don't wait on each other. Keys
+//! are meant to be Zobrist keys, such as `GameState::key`, whose bits are
+//! all equally random.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Which entry keeps a slot when a new one comes for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Replacement {
+    /// The new entry, always.
+    Always,
+    /// The new entry, unless the one there was searched deeper.
+    Deeper,
+    /// As `Deeper`, except that entries from before the last
+    /// `new_generation` give way to any new one.
+    Aged,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry<V> {
+    pub key: u64,
+    /// How deep the value was searched, for the replacement policy.
+    pub depth: u32,
+    pub value: V,
+    generation: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TableStats {
+    /// Lookups that found their key.
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries stored, in an empty slot or replacing another.
+    pub stores: u64,
+    /// Entries stored over one with a different key.
+    pub replaced: u64,
+    /// Entries dropped to keep the one in their slot.
+    pub rejected: u64,
+}
+
+impl TableStats {
+    /// Fraction of lookups that found their key, or 0 before any.
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / (self.hits + self.misses).max(1) as f64
+    }
+}
+
+/// Slots sharing a lock.
+type Shard<V> = Mutex<Vec<Option<Entry<V>>>>;
+
+pub struct TranspositionTable<V> {
+    shards: Vec<Shard<V>>,
+    shard_bits: u32,
+    /// Slots in each shard.
+    slots: usize,
+    replacement: Replacement,
+    generation: AtomicU32,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stores: AtomicU64,
+    replaced: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl<V: Clone> TranspositionTable<V> {
+    /// A table with room for `capacity` entries, rounded up to a power of
+    /// two.
+    pub fn new(capacity: usize, replacement: Replacement) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        let shards = capacity.min(64);
+        Self {
+            shards: (0..shards)
+                .map(|_| Mutex::new(vec![None; capacity / shards]))
+                .collect(),
+            shard_bits: shards.trailing_zeros(),
+            slots: capacity / shards,
+            replacement,
+            generation: AtomicU32::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            stores: AtomicU64::new(0),
+            replaced: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shards.len() * self.slots
+    }
+
+    pub fn replacement(&self) -> Replacement {
+        self.replacement
+    }
+
+    /// The shard and slot `key` goes in.
+    fn slot(&self, key: u64) -> (&Shard<V>, usize) {
+        let shard = &self.shards[(key & ((1 << self.shard_bits) - 1)) as usize];
+        (shard, (key >> self.shard_bits) as usize & (self.slots - 1))
+    }
+
+    /// The entry stored for `key`, if it's still there.
+    pub fn get(&self, key: u64) -> Option<Entry<V>> {
+        let (shard, slot) = self.slot(key);
+        let found = shard.lock().unwrap()[slot]
+            .as_ref()
+            .filter(|entry| entry.key == key)
+            .cloned();
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Stores `value` for `key`, searched to `depth`, unless the replacement
+    /// policy keeps what's in its slot. Returns whether it was stored.
+    pub fn insert(&self, key: u64, depth: u32, value: V) -> bool {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let (shard, slot) = self.slot(key);
+        let mut slots = shard.lock().unwrap();
+        let replaced = match &slots[slot] {
+            None => false,
+            Some(old) => {
+                let keep = match self.replacement {
+                    Replacement::Always => false,
+                    Replacement::Deeper => old.depth > depth,
+                    Replacement::Aged => old.generation == generation && old.depth > depth,
+                };
+                if keep {
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                old.key != key
+            }
+        };
+        slots[slot] = Some(Entry {
+            key,
+            depth,
+            value,
+            generation,
+        });
+        self.stores.fetch_add(1, Ordering::Relaxed);
+        if replaced {
+            self.replaced.fetch_add(1, Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// Marks every entry stored so far as old, for `Replacement::Aged`,
+    /// such as when a search moves on to the next position.
+    pub fn new_generation(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Entries stored, counting every slot.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().iter().flatten().count())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Empties every slot, keeping the statistics.
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap().fill(None);
+        }
+    }
+
+    pub fn stats(&self) -> TableStats {
+        TableStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            stores: self.stores.load(Ordering::Relaxed),
+            replaced: self.replaced.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        for counter in [
+            &self.hits,
+            &self.misses,
+            &self.stores,
+            &self.replaced,
+            &self.rejected,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let table = TranspositionTable::new(100, Replacement::Deeper);
+        assert_eq!(table.capacity(), 128);
+        assert!(table.insert(1, 3, "deep"));
+        assert_eq!(table.get(1).unwrap().value, "deep");
+        assert_eq!(table.get(2), None);
+        // 129 shares the slot of 1, and is dropped for being shallower.
+        assert!(!table.insert(129, 2, "shallow"));
+        assert!(table.insert(129, 3, "as deep"));
+        assert_eq!(table.get(1), None);
+        let stats = table.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!((stats.stores, stats.replaced, stats.rejected), (2, 1, 1));
+
+        let aged = TranspositionTable::new(128, Replacement::Aged);
+        aged.insert(1, 3, "old");
+        assert!(!aged.insert(129, 2, "new"));
+        aged.new_generation();
+        assert!(aged.insert(129, 2, "new"));
+        let always = TranspositionTable::new(128, Replacement::Always);
+        always.insert(1, 3, "old");
+        assert!(always.insert(129, 0, "new"));
+
+        // Threads storing and finding their own keys never go over the
+        // capacity.
+        let table = TranspositionTable::new(1 << 10, Replacement::Always);
+        (0..4096u64).into_par_iter().for_each(|key| {
+            let key = crate::dice::derive_seed(0, key);
+            table.insert(key, 0, key);
+            if let Some(entry) = table.get(key) {
+                assert_eq!(entry.value, key);
+            }
+        });
+        assert!(table.len() <= 1024 && table.len() > 900);
+        assert_eq!(table.stats().hits + table.stats().misses, 4096);
+        table.clear();
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+//! Tunes the weights of a `HeuristicBot` by self-play.
+//!
+//! Uses SPSA: every step nudges all the weights at once, both ways along a
+//! random direction, plays each version against the reference pool on the
+//! same dice, and moves the weights towards the one that won more. The
+//! games of each evaluation are played in parallel.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bots::{average_bot, choose_closest_to_target, choose_random, Bot, Bots};
+use crate::dice::{derive_seed, SplitDice};
+use crate::game_state::GameState;
+use crate::heuristic::{HeuristicBot, Weights, WeightsError};
+use crate::simulator::simulate_to_finish;
+
+/// The bots candidates are measured against by default.
+pub fn reference_pool() -> Vec<Box<dyn Bot>> {
+    vec![
+        Box::new(average_bot),
+        Box::new(choose_closest_to_target),
+        Box::new(choose_random),
+    ]
+}
+
+#[derive(Clone)]
+pub struct TuningConfig {
+    /// Position every game starts from, which also sets the rules.
+    pub start: GameState,
+    /// Bots the candidates play against, taking turns.
+    pub pool: Vec<Box<dyn Bot>>,
+    /// Games played to measure each candidate.
+    pub games: u32,
+    pub seed: u64,
+    /// Size of the nudges, relative to each weight's scale.
+    pub perturbation: f64,
+    /// Size of the steps, relative to each weight's scale.
+    pub learning_rate: f64,
+    /// Where to save the tuner's progress after every step.
+    pub checkpoint: Option<PathBuf>,
+    /// Where to write the best weights whenever they improve.
+    pub output: Option<PathBuf>,
+}
+
+impl Default for TuningConfig {
+    fn default() -> Self {
+        Self {
+            start: GameState::default(),
+            pool: reference_pool(),
+            games: 200,
+            seed: 0,
+            perturbation: 0.2,
+            learning_rate: 1.0,
+            checkpoint: None,
+            output: None,
+        }
+    }
+}
+
+/// Everything needed to carry on tuning where it stopped.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Checkpoint {
+    /// Steps taken so far.
+    pub step: u32,
+    pub weights: Weights,
+    /// How far each weight moves, taken from the starting weights so that
+    /// small and large weights change at the same relative rate.
+    pub scale: [f64; 8],
+    /// Best weights on the validation games so far, which are the same for
+    /// every candidate.
+    pub best: Weights,
+    /// Win rate of `best` on the validation games, once measured.
+    pub best_win_rate: Option<f64>,
+}
+
+pub struct Tuner {
+    pub config: TuningConfig,
+    pub state: Checkpoint,
+}
+
+impl Tuner {
+    pub fn new(config: TuningConfig, weights: Weights) -> Self {
+        Self {
+            config,
+            state: Checkpoint {
+                step: 0,
+                weights,
+                scale: weights.to_array().map(|weight| weight.abs().max(0.1)),
+                best: weights,
+                best_win_rate: None,
+            },
+        }
+    }
+
+    /// Carries on from the checkpoint saved at `path`.
+    pub fn resume(config: TuningConfig, path: impl AsRef<Path>) -> Result<Self, WeightsError> {
+        let state = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(Self { config, state })
+    }
+
+    /// Fraction of `config.games` games won with `weights` against the
+    /// pool, rotating seats and opponents, with the dice seeded by `seed`.
+    ///
+    /// Each seat rolls from its own stream, so weights compared on the same
+    /// seed get the same rolls even where they play differently.
+    pub fn win_rate(&self, weights: &Weights, seed: u64) -> f64 {
+        let TuningConfig {
+            start, pool, games, ..
+        } = &self.config;
+        let seats = &start.rules.seats;
+        let candidate = HeuristicBot::new(*weights);
+        let wins = (0..*games)
+            .into_par_iter()
+            .filter(|&game| {
+                let game = game as usize;
+                let side = seats[game % seats.len()].side(&start.rules);
+                let opponent = &pool[game / seats.len() % pool.len()];
+                let mut bots = Bots::seated(&start.rules, |player| {
+                    if side.contains(player) {
+                        candidate.box_clone()
+                    } else {
+                        opponent.box_clone()
+                    }
+                });
+                let game = game as u64;
+                let mut dice =
+                    SplitDice::new(derive_seed(seed, 2 * game), derive_seed(seed, 2 * game + 1));
+                simulate_to_finish(&mut start.clone(), &mut bots, &mut dice) == side
+            })
+            .count();
+        wins as f64 / (*games).max(1) as f64
+    }
+
+    fn validation_seed(&self) -> u64 {
+        derive_seed(self.config.seed, u64::MAX)
+    }
+
+    /// Takes one step, then keeps the new weights if they're the best on
+    /// the validation games and saves the checkpoint.
+    pub fn step(&mut self) -> Result<(), WeightsError> {
+        let k = self.state.step as f64;
+        let a = self.config.learning_rate / (k + 11.0).powf(0.602);
+        let c = self.config.perturbation / (k + 1.0).powf(0.101);
+        let mut rng = StdRng::seed_from_u64(derive_seed(self.config.seed, self.state.step as u64));
+        let direction = [(); 8].map(|_| if rng.gen() { 1.0 } else { -1.0 });
+        let seed = rng.gen();
+
+        let weights = self.state.weights.to_array();
+        let nudged = |sign: f64| {
+            let mut nudged = weights;
+            for i in 0..8 {
+                nudged[i] += sign * c * direction[i] * self.state.scale[i];
+            }
+            Weights::from_array(nudged)
+        };
+        let gain = self.win_rate(&nudged(1.0), seed) - self.win_rate(&nudged(-1.0), seed);
+        let mut stepped = weights;
+        for i in 0..8 {
+            stepped[i] += a * gain / (2.0 * c * direction[i]) * self.state.scale[i];
+        }
+        self.state.weights = Weights::from_array(stepped);
+        self.state.step += 1;
+
+        let validation = self.validation_seed();
+        let best_win_rate = match self.state.best_win_rate {
+            Some(win_rate) => win_rate,
+            None => self.win_rate(&self.state.best, validation),
+        };
+        self.state.best_win_rate = Some(best_win_rate);
+        let win_rate = self.win_rate(&self.state.weights, validation);
+        if win_rate > best_win_rate {
+            self.state.best = self.state.weights;
+            self.state.best_win_rate = Some(win_rate);
+            if let Some(output) = &self.config.output {
+                self.state.best.save(output)?;
+            }
+        }
+
+        if let Some(checkpoint) = &self.config.checkpoint {
+            fs::write(checkpoint, serde_json::to_string_pretty(&self.state)?)?;
+        }
+        Ok(())
+    }
+
+    /// Steps until `steps` have been taken in all, returning the best
+    /// weights found.
+    pub fn run(&mut self, steps: u32) -> Result<Weights, WeightsError> {
+        while self.state.step < steps {
+            self.step()?;
+        }
+        Ok(self.state.best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleSet;
+    use crate::Player;
+
+    #[test]
+    fn test_tuning() {
+        // A directory of its own, so that concurrent runs don't share the
+        // files.
+        let dir =
+            std::env::temp_dir().join(format!("ludo_engine_test_tuning_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = TuningConfig {
+            start: GameState::with_rules(Default::default(), Player::First, RuleSet::two_player()),
+            pool: vec![Box::new(choose_random)],
+            games: 32,
+            checkpoint: Some(dir.join("checkpoint.json")),
+            output: Some(dir.join("best.json")),
+            ..Default::default()
+        };
+        let mut tuner = Tuner::new(config.clone(), Weights::default());
+        tuner.run(2).unwrap();
+        assert_ne!(tuner.state.weights, Weights::default());
+
+        // Stopping after one step and resuming ends up in the same place.
+        let mut stopped = Tuner::new(config.clone(), Weights::default());
+        stopped.run(1).unwrap();
+        let mut resumed = Tuner::resume(config, dir.join("checkpoint.json")).unwrap();
+        assert_eq!(resumed.state, stopped.state);
+        resumed.run(2).unwrap();
+        assert_eq!(resumed.state, tuner.state);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,19 @@
-use std::collections::HashMap;
+use std::fmt::Display;
 use std::iter;
-use std::sync::mpsc;
 
-use enum_map::EnumMap;
+use itertools::Itertools;
 use rand::Rng;
 
-use crate::board::{Action, Team};
-use crate::bots::Bot;
+use crate::board::{Action, Side};
+use crate::bots::Bots;
+use crate::dice::derive_seed;
 use crate::game_state::GameState;
-use crate::simulator::simulate_to_finish;
-use crate::Player;
+use crate::record::format_action;
+use crate::rollout::{RolloutEngine, Rollouts};
 
-use rayon::{prelude::*, current_num_threads};
+use rayon::prelude::*;
 
-pub fn roll_dice(rng: &mut impl Rng) -> u8 {
+pub fn roll_dice(rng: &mut (impl Rng + ?Sized)) -> u8 {
     rng.gen_range(1u8..=6)
 }
 
@@ -23,13 +23,132 @@ pub struct Stats {
     lost: u64,
 }
 
+/// Natural log of the gamma function for `x` of at least a half, by
+/// Lanczos' approximation, good to about 15 digits.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .zip(1..)
+        .fold(COEFFICIENTS[0], |sum, (c, i)| sum + c / (x + i as f64));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Continued fraction of the incomplete beta function, evaluated with
+/// Lentz's method. It converges quickly for `x` below the mean, in about
+/// the square root of `a` and `b` terms, so it's cut off after
+/// `MAX_TERMS`, enough for millions of games.
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const MAX_TERMS: u32 = 10_000;
+    const TINY: f64 = 1e-300;
+    let away_from_zero = |v: f64| if v.abs() < TINY { TINY } else { v };
+    let mut c = 1.0;
+    let mut d = 1.0 / away_from_zero(1.0 - (a + b) * x / (a + 1.0));
+    let mut fraction = d;
+    for m in 1..=MAX_TERMS {
+        let m = m as f64;
+        let even = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        for step in [even, odd] {
+            d = 1.0 / away_from_zero(1.0 + step * d);
+            c = away_from_zero(1.0 + step / c);
+            fraction *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    fraction
+}
+
+/// Probability that a Beta(`a`, `b`) variable, with whole `a` and `b`, is at
+/// most `x`.
+fn beta_cdf(a: u64, b: u64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let (a, b) = (a as f64, b as f64);
+    let ln_front = ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (-x).ln_1p();
+    // The fraction only converges quickly below the mean, so above it the
+    // other tail is worked out instead.
+    if x < (a + 1.0) / (a + b + 2.0) {
+        ln_front.exp() * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - ln_front.exp() * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
 impl Stats {
     fn new() -> Self {
         Self { won: 0, lost: 0 }
     }
 
+    /// Fraction of the games won, or a half before any have been played,
+    /// which only `games` tells apart from a real estimate.
     pub fn get_percent(&self) -> f64 {
-        self.won as f64 / (self.won + self.lost) as f64
+        if self.games() == 0 {
+            return 0.5;
+        }
+        self.won as f64 / self.games() as f64
+    }
+
+    pub fn wins(&self) -> u64 {
+        self.won
+    }
+
+    pub fn games(&self) -> u64 {
+        self.won + self.lost
+    }
+
+    /// Wilson score interval for the win rate, `z` standard deviations wide
+    /// on each side, such as `1.96` for 95%. Unlike the normal
+    /// approximation, it stays inside `[0, 1]` and is sensible for a handful
+    /// of games or a win rate near 0 or 1.
+    pub fn wilson_interval(&self, z: f64) -> (f64, f64) {
+        let n = self.games() as f64;
+        if n == 0.0 {
+            return (0.0, 1.0);
+        }
+        let p = self.won as f64 / n;
+        let z2 = z * z;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half = z / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+        ((center - half).max(0.0), (center + half).min(1.0))
+    }
+
+    /// Central interval holding `level` of the posterior of the win rate,
+    /// starting from a uniform prior.
+    pub fn credible_interval(&self, level: f64) -> (f64, f64) {
+        let (a, b) = (self.won + 1, self.lost + 1);
+        let quantile = |q: f64| {
+            let (mut low, mut high) = (0.0, 1.0);
+            for _ in 0..50 {
+                let mid = (low + high) / 2.0;
+                if beta_cdf(a, b, mid) < q {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            (low + high) / 2.0
+        };
+        let tail = (1.0 - level) / 2.0;
+        (quantile(tail), quantile(1.0 - tail))
     }
 
     pub fn add_win(&mut self) {
@@ -38,6 +157,19 @@ impl Stats {
     pub fn add_loss(&mut self) {
         self.lost += 1;
     }
+
+    pub(crate) fn add(&mut self, result: GameResult) {
+        match result {
+            GameResult::Win => self.add_win(),
+            GameResult::Loss => self.add_loss(),
+        }
+    }
+
+    /// Adds the games counted in `other`.
+    pub fn merge(&mut self, other: Stats) {
+        self.won += other.won;
+        self.lost += other.lost;
+    }
 }
 
 pub enum GameResult {
@@ -45,26 +177,14 @@ pub enum GameResult {
     Loss,
 }
 
-pub fn game_simulator_iterator(
-    state: GameState,
-    team: Team,
-    bots: EnumMap<Player, Bot>,
-) -> impl Iterator<Item = GameResult> {
-    let chunk_size = current_num_threads();
-    iter::repeat_with(move || 0..chunk_size).flat_map(move |chunk| {
-        let (tx, rx) = mpsc::sync_channel(chunk_size);
-        chunk
-            .into_par_iter()
-            .map(move |_| {
-                if simulate_to_finish(&mut state.clone(), &bots) == team {
-                    GameResult::Win
-                } else {
-                    GameResult::Loss
-                }
-            })
-            .for_each(|gr| tx.send(gr).unwrap());
-        rx
-    })
+/// Where the rollouts comparing several actions get their dice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RolloutDice {
+    /// Rollout `n` of every action gets the same rolls, so that the
+    /// comparison isn't swamped by some actions getting luckier dice.
+    pub common: bool,
+    /// Every other rollout gets the rolls of the one before upside down.
+    pub antithetic: bool,
 }
 
 pub fn stats_calculator(mut iter: impl Iterator<Item = GameResult>) -> impl Iterator<Item = Stats> {
@@ -73,10 +193,7 @@ pub fn stats_calculator(mut iter: impl Iterator<Item = GameResult>) -> impl Iter
     iter::from_fn(move || {
         if i > 0 {
             if let Some(res) = iter.next() {
-                match res {
-                    GameResult::Win => stats.add_win(),
-                    GameResult::Loss => stats.add_loss(),
-                }
+                stats.add(res);
             } else {
                 return None;
             }
@@ -86,33 +203,456 @@ pub fn stats_calculator(mut iter: impl Iterator<Item = GameResult>) -> impl Iter
     })
 }
 
-pub fn stats_per_action(
+/// Rollouts for each action `dice` allows, in a fixed order, with games
+/// seeded from `seed` and, unless the dice are common, the action's place
+/// in that order.
+pub(crate) fn action_rollouts(
     initial_state: GameState,
     dice: u8,
-    team: Team,
-    bots: EnumMap<Player, Bot>,
-) -> impl Iterator<Item = HashMap<Action, Stats>> {
-    let actions = initial_state.get_actions(dice);
-    let mut state_by_action =
-        HashMap::<Action, (GameState, Stats)>::from_iter(actions.iter().map(|&action| {
-            let mut s = initial_state.clone();
-            s.roll(dice, |_state, actions| {
-                actions.iter().position(|&a| a == action).unwrap()
-            });
-            (action, (s, Stats::new()))
-        }));
-    let mut iterators = HashMap::new();
-    for (action, &mut (initial_state, _stats)) in state_by_action.iter_mut() {
-        let sim = game_simulator_iterator(initial_state, team, bots);
-        let it = stats_calculator(sim);
-        iterators.insert(action.clone(), it);
+    side: Side,
+    bots: Bots,
+    rollout_dice: RolloutDice,
+    seed: u64,
+) -> Vec<(Action, Rollouts)> {
+    // Sorted so that each action gets the same seed on every run.
+    initial_state
+        .get_actions(dice)
+        .into_iter()
+        .sorted_by_key(|action| (action.player as usize, action.piece))
+        .zip(0..)
+        .map(|(action, i)| {
+            let mut state = initial_state.clone();
+            state
+                .apply_action(dice, action)
+                .expect("actions from get_actions are legal");
+            let seed = if rollout_dice.common {
+                seed
+            } else {
+                derive_seed(seed, i)
+            };
+            (
+                action,
+                Rollouts::new(state, side, bots.clone(), rollout_dice, seed),
+            )
+        })
+        .collect()
+}
+
+/// How rollouts are shared out between the actions being compared.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+    /// The same number of rollouts for every action.
+    Fixed(usize),
+    /// Rollouts in batches, dropping the actions whose Wilson interval, `z`
+    /// standard deviations wide, lies wholly below the best one's, until
+    /// one is left or `budget` rollouts have been played in all. A lone
+    /// action still gets a batch.
+    Racing { budget: usize, batch: usize, z: f64 },
+    /// Splits `budget` rollouts evenly between rounds, keeping the better
+    /// half of the actions after each, until one is left. A lone action
+    /// gets the single round.
+    SuccessiveHalving { budget: usize },
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::Racing {
+            budget: 2000,
+            batch: 20,
+            z: 2.0,
+        }
     }
+}
 
-    iter::from_fn(move || {
-        iterators
-            .iter_mut()
-            .map(|(&action, it)| it.next().map(|v| (action, v)))
-            .collect::<Option<Vec<_>>>()
-            .map(HashMap::from_iter)
-    })
+/// How the rollouts comparing several actions are shared out, and where
+/// they get their dice.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SampleOptions {
+    pub sampling: Sampling,
+    pub rollout_dice: RolloutDice,
+}
+
+impl SampleOptions {
+    /// `games` rollouts of every action, with the default dice.
+    pub fn fixed(games: usize) -> Self {
+        Self {
+            sampling: Sampling::Fixed(games),
+            ..Default::default()
+        }
+    }
+}
+
+/// Stats from rolling out each action `dice` allows with `engine`, shared
+/// out and rolled as `options` asks. Once the engine is stopped, the stats
+/// are those of the games played so far.
+pub fn sample_actions(
+    engine: &RolloutEngine,
+    initial_state: GameState,
+    dice: u8,
+    side: Side,
+    bots: Bots,
+    options: SampleOptions,
+    seed: u64,
+) -> Vec<(Action, Stats)> {
+    let SampleOptions {
+        sampling,
+        rollout_dice,
+    } = options;
+    let mut actions = action_rollouts(initial_state, dice, side, bots, rollout_dice, seed)
+        .into_iter()
+        .map(|(action, rollouts)| (action, rollouts, Stats::new()))
+        .collect_vec();
+    // Plays the next `games` games of every action alive in one batch.
+    let play = |actions: &mut [(Action, Rollouts, Stats)], alive: &[usize], games: usize| {
+        let batches = alive
+            .iter()
+            .map(|&i| {
+                let (_, rollouts, stats) = &actions[i];
+                (rollouts, stats.games()..stats.games() + games as u64)
+            })
+            .collect_vec();
+        let results = engine.run(&batches);
+        for (&i, stats) in alive.iter().zip(results) {
+            actions[i].2.merge(stats);
+        }
+    };
+    let mut alive = (0..actions.len()).collect_vec();
+
+    match sampling {
+        Sampling::Fixed(games) => play(&mut actions, &alive, games),
+        Sampling::Racing { budget, batch, z } => {
+            let batch = batch.max(1);
+            let mut played = 0;
+            while !alive.is_empty()
+                && (alive.len() > 1 || played == 0)
+                && played < budget
+                && !engine.is_stopped()
+            {
+                let games = batch.min((budget - played).div_ceil(alive.len()));
+                play(&mut actions, &alive, games);
+                played += games * alive.len();
+                let best_low = alive
+                    .iter()
+                    .map(|&i| actions[i].2.wilson_interval(z).0)
+                    .fold(0.0, f64::max);
+                alive.retain(|&i| actions[i].2.wilson_interval(z).1 >= best_low);
+            }
+        }
+        Sampling::SuccessiveHalving { budget } => {
+            let rounds = (alive.len() as f64).log2().ceil().max(1.0) as usize;
+            let mut first = true;
+            while !alive.is_empty() && (alive.len() > 1 || first) && !engine.is_stopped() {
+                first = false;
+                play(
+                    &mut actions,
+                    &alive,
+                    (budget / (rounds * alive.len())).max(1),
+                );
+                alive.sort_by(|&a, &b| {
+                    let percent = |i: usize| actions[i].2.get_percent();
+                    percent(b).total_cmp(&percent(a))
+                });
+                alive.truncate(alive.len().div_ceil(2));
+            }
+        }
+    }
+
+    actions
+        .into_iter()
+        .map(|(action, _, stats)| (action, stats))
+        .collect()
+}
+
+/// How much sharing the dice between actions, and pairing each rollout
+/// with its mirror image, cuts the variance of the estimates that rank
+/// them.
+#[derive(Clone, Debug)]
+pub struct VarianceReport {
+    /// Rollouts per action, in antithetic pairs.
+    pub rollouts: usize,
+    pub actions: Vec<ActionVariance>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ActionVariance {
+    pub action: Action,
+    pub win_rate: f64,
+    /// Variance of `win_rate` had the rollouts been independent.
+    pub independent: f64,
+    /// Variance of `win_rate`, from the spread of the antithetic pairs.
+    pub antithetic: f64,
+    /// Variance of the gap to the best action's win rate had each action
+    /// had its own dice.
+    pub gap_independent: f64,
+    /// Variance of the same gap with common, antithetic dice, from the
+    /// spread of the gaps between paired rollouts.
+    pub gap_common: f64,
+}
+
+impl VarianceReport {
+    /// How many times smaller the variance of the gaps to the best action
+    /// is with common, antithetic dice.
+    pub fn reduction(&self) -> f64 {
+        let independent = self.actions.iter().map(|a| a.gap_independent).sum::<f64>();
+        let common = self.actions.iter().map(|a| a.gap_common).sum::<f64>();
+        if common == 0.0 {
+            return 1.0;
+        }
+        independent / common
+    }
+}
+
+impl Display for VarianceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<10} {:>8} {:>12} {:>12} {:>12} {:>12}",
+            "action", "win rate", "independent", "antithetic", "gap indep.", "gap common"
+        )?;
+        for action in &self.actions {
+            writeln!(
+                f,
+                "{:<10} {:>8.3} {:>12.2e} {:>12.2e} {:>12.2e} {:>12.2e}",
+                format_action(&action.action),
+                action.win_rate,
+                action.independent,
+                action.antithetic,
+                action.gap_independent,
+                action.gap_common
+            )?;
+        }
+        write!(
+            f,
+            "{} rollouts per action, gap variance {:.2}x smaller",
+            self.rollouts,
+            self.reduction()
+        )
+    }
+}
+
+/// Unbiased variance of `values` around their mean.
+fn sample_variance(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64
+}
+
+/// Rolls out every action `dice` allows `rollouts` times, rounded up to
+/// whole antithetic pairs, with common dice, and measures how much that
+/// cut the variance compared to independent rollouts.
+pub fn variance_report(
+    initial_state: GameState,
+    dice: u8,
+    side: Side,
+    bots: Bots,
+    rollouts: usize,
+    seed: u64,
+) -> VarianceReport {
+    let rollout_dice = RolloutDice {
+        common: true,
+        antithetic: true,
+    };
+    let pairs = rollouts.div_ceil(2).max(1);
+    let results = action_rollouts(initial_state, dice, side, bots, rollout_dice, seed)
+        .into_iter()
+        .map(|(action, rollouts)| {
+            let wins = (0..2 * pairs as u64)
+                .into_par_iter()
+                .map(|game| matches!(rollouts.play(game), GameResult::Win) as u8 as f64)
+                .collect::<Vec<_>>();
+            let pair_means = wins
+                .chunks(2)
+                .map(|pair| (pair[0] + pair[1]) / 2.0)
+                .collect_vec();
+            (action, pair_means)
+        })
+        .collect_vec();
+
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    let independent = |win_rate: f64| win_rate * (1.0 - win_rate) / (2 * pairs) as f64;
+    let best = results
+        .iter()
+        .position_max_by(|(_, a), (_, b)| mean(a).total_cmp(&mean(b)))
+        .unwrap_or(0);
+    let actions = results
+        .iter()
+        .enumerate()
+        .map(|(i, (action, pair_means))| {
+            let win_rate = mean(pair_means);
+            let best_means = &results[best].1;
+            let gaps = pair_means
+                .iter()
+                .zip(best_means)
+                .map(|(a, b)| a - b)
+                .collect_vec();
+            ActionVariance {
+                action: *action,
+                win_rate,
+                independent: independent(win_rate),
+                antithetic: sample_variance(pair_means) / pairs as f64,
+                gap_independent: if i == best {
+                    0.0
+                } else {
+                    independent(win_rate) + independent(mean(best_means))
+                },
+                gap_common: sample_variance(&gaps) / pairs as f64,
+            }
+        })
+        .collect();
+    VarianceReport {
+        rollouts: 2 * pairs,
+        actions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Team;
+    use crate::bots::{all_seats, choose_random};
+    use crate::rules::RuleSet;
+    use crate::Player;
+
+    #[test]
+    fn test_seeded_results() {
+        let results = |seed| {
+            let rollouts = Rollouts::new(
+                GameState::default(),
+                Team::Odd.into(),
+                all_seats(choose_random),
+                RolloutDice::default(),
+                seed,
+            );
+            RolloutEngine::default()
+                .results(&rollouts, 0..50)
+                .into_iter()
+                .map(|result| matches!(result, GameResult::Win))
+                .collect_vec()
+        };
+        assert_eq!(results(3), results(3));
+        assert_ne!(results(3), results(4));
+    }
+
+    #[test]
+    fn test_intervals() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.get_percent(), 0.5);
+        assert_eq!(stats.wilson_interval(1.96), (0.0, 1.0));
+        for game in 0..10 {
+            if game < 8 {
+                stats.add_win();
+            } else {
+                stats.add_loss();
+            }
+        }
+        let (low, high) = stats.wilson_interval(1.96);
+        assert!((low - 0.490).abs() < 1e-3 && (high - 0.943).abs() < 1e-3);
+        // Beta(9, 3) quantiles.
+        let (low, high) = stats.credible_interval(0.95);
+        assert!((low - 0.482).abs() < 1e-3 && (high - 0.940).abs() < 1e-3);
+        // Close to the normal approximation after a million games.
+        let many = Stats {
+            won: 600_000,
+            lost: 400_000,
+        };
+        let (low, high) = many.credible_interval(0.95);
+        assert!((low - 0.59904).abs() < 1e-4 && (high - 0.60096).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_sampling() {
+        let mut state =
+            GameState::with_rules(Default::default(), Player::First, RuleSet::two_player());
+        let last = state.rules.geometry.last_place();
+        // Piece 0 can take the enemy's last piece, which is about to finish.
+        state.board_mut().players[Player::First].pieces_positions = [10, 30, last, last];
+        state.board_mut().players[Player::Third].pieces_positions = [last, last, last, 34];
+        let sample = |sampling| {
+            let stats = sample_actions(
+                &RolloutEngine::default(),
+                state.clone(),
+                6,
+                Player::First.side(&state.rules),
+                all_seats(choose_random),
+                SampleOptions {
+                    sampling,
+                    ..Default::default()
+                },
+                1,
+            );
+            let best = stats
+                .iter()
+                .max_by(|(_, a), (_, b)| a.get_percent().total_cmp(&b.get_percent()))
+                .map(|(action, _)| action.piece);
+            let games = stats.iter().map(|(_, stats)| stats.games()).sum::<u64>();
+            (best, games)
+        };
+        assert_eq!(sample(Sampling::Fixed(10)), (Some(0), 20));
+        // The engine plays the same games for a fixed number of rollouts.
+        let side = Player::First.side(&state.rules);
+        let fixed = sample_actions(
+            &RolloutEngine::default(),
+            state.clone(),
+            6,
+            side,
+            all_seats(choose_random),
+            SampleOptions::fixed(10),
+            1,
+        );
+        let per_action = RolloutEngine::default().stats_per_action(
+            state.clone(),
+            6,
+            side,
+            all_seats(choose_random),
+            10,
+            1,
+        );
+        assert!(fixed
+            .iter()
+            .all(|(action, stats)| per_action[action].wins() == stats.wins()));
+        let (best, games) = sample(Sampling::SuccessiveHalving { budget: 40 });
+        assert!(best == Some(0) && games <= 40);
+        let (best, games) = sample(Sampling::Racing {
+            budget: 200,
+            batch: 10,
+            z: 2.0,
+        });
+        assert!(best == Some(0) && games < 200);
+
+        // With a single action to play, it's still rolled out.
+        let start = GameState::with_rules(Default::default(), Player::First, RuleSet::two_player());
+        for (sampling, played) in [
+            (Sampling::default(), 20),
+            (Sampling::SuccessiveHalving { budget: 40 }, 40),
+        ] {
+            let stats = sample_actions(
+                &RolloutEngine::default(),
+                start.clone(),
+                6,
+                Player::First.side(&start.rules),
+                all_seats(choose_random),
+                SampleOptions {
+                    sampling,
+                    ..Default::default()
+                },
+                1,
+            );
+            assert_eq!(stats.len(), 1);
+            assert_eq!(stats[0].1.games(), played);
+        }
+
+        let report = variance_report(
+            state.clone(),
+            6,
+            Player::First.side(&state.rules),
+            all_seats(choose_random),
+            40,
+            1,
+        );
+        assert_eq!(report.actions.len(), 2);
+        assert!(report.reduction() > 1.0);
+        assert!(report.to_string().contains("1a:10-16"));
+    }
 }
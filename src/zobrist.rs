@@ -0,0 +1,120 @@
+//! Zobrist keys for boards and game states.
+//!
+//! Every piece in every position, every player to move, every number of
+//! sixes rolled in a row and every place in the finishing order gets a
+//! random 64-bit number, and a key is the exclusive or of the numbers of
+//! what's in the position. Moving a piece then only changes the key by the
+//! numbers of where it left and where it went. The numbers are the same on
+//! every run, so keys can be stored.
+
+use lazy_static::lazy_static;
+
+use crate::board::PiecePosition;
+use crate::dice::derive_seed;
+use crate::Player;
+
+/// Seed the numbers are drawn from.
+const SEED: u64 = 0x6c75_646f_5f7a_6f62;
+
+struct Keys {
+    pieces: Vec<[[u64; 256]; 4]>,
+    turns: [u64; 6],
+    sixes: [u64; 256],
+    finishers: [[u64; 6]; 6],
+}
+
+lazy_static! {
+    static ref KEYS: Keys = {
+        let mut stream = 0;
+        let mut next = || {
+            stream += 1;
+            derive_seed(SEED, stream)
+        };
+        let pieces = (0..6)
+            .map(|_| [(); 4].map(|_| [(); 256].map(|_| next())))
+            .collect();
+        Keys {
+            pieces,
+            turns: [(); 6].map(|_| next()),
+            sixes: [(); 256].map(|_| next()),
+            finishers: [(); 6].map(|_| [(); 6].map(|_| next())),
+        }
+    };
+}
+
+/// Number of `player`'s piece `piece` being at `position`.
+pub fn piece(player: Player, piece: u8, position: PiecePosition) -> u64 {
+    KEYS.pieces[player as usize][piece as usize & 3][position as usize]
+}
+
+/// Number of `player` being the one to move.
+pub fn turn(player: Player) -> u64 {
+    KEYS.turns[player as usize]
+}
+
+/// Number of the player to move having rolled `six_rolled` sixes in a row.
+pub fn sixes(six_rolled: u8) -> u64 {
+    KEYS.sixes[six_rolled as usize]
+}
+
+/// Number of `player` having finished in place `place`, counting from `0`.
+pub fn finisher(place: usize, player: Player) -> u64 {
+    KEYS.finishers[place % 6][player as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::board::{Action, Board};
+    use crate::game_state::GameState;
+    use crate::rules::RuleSet;
+    use crate::Player;
+
+    #[test]
+    fn test_keys() {
+        let mut state = GameState::default();
+        let start = state.key();
+        assert_eq!(start, state.clone().key());
+        assert_eq!(state.board().key(), Board::default().key());
+
+        let action = Action {
+            player: Player::First,
+            piece: 0,
+            from: 0,
+            to: 1,
+        };
+        state.apply_action(6, action).unwrap();
+        // Same pieces, but another six rolled.
+        let mut moved = GameState::default();
+        moved.board_mut().players[Player::First].pieces_positions[0] = 1;
+        assert_eq!(state.board().key(), moved.board().key());
+        assert_ne!(state.key(), moved.key());
+        assert_ne!(state.key(), start);
+
+        // Keys kept up to date move by move match keys worked out afresh,
+        // captures and finishes included.
+        let mut rng = StdRng::seed_from_u64(1);
+        for rules in [
+            RuleSet::classic(),
+            RuleSet::three_player(),
+            RuleSet::six_player(),
+        ] {
+            let mut state = GameState::with_rules(Board::default(), Player::First, rules);
+            while !state.is_over() {
+                let dice = rng.gen_range(1..=6);
+                state
+                    .roll(dice, |_, actions| rng.gen_range(0..actions.len()))
+                    .unwrap();
+                let copy = GameState::from_parts(
+                    *state.board(),
+                    state.turn,
+                    (*state.rules).clone(),
+                    state.six_rolled(),
+                    Some(state.finishing_order().to_vec()),
+                );
+                assert_eq!(state.key(), copy.key());
+            }
+        }
+    }
+}